@@ -0,0 +1,146 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::image::Image;
+use crate::scene::Scene;
+use crate::track::{CameraState, Keyframe, KeyframeTrack};
+
+/// Camera positions to fly through, from a TOML file of `[[keyframe]]`
+/// tables with a `position`, a `forward` vector and optionally an `up`
+/// vector like in scene files, and optionally a `time` in seconds. Either
+/// every keyframe has a time or none has, in which case they are spread
+/// evenly over the animation. The camera moves between them along a
+/// `KeyframeTrack`.
+#[derive(Debug, Clone)]
+pub struct CameraPath {
+    track: KeyframeTrack,
+}
+
+/// When the frames of an animation are taken. Frame `i` shows the path at
+/// `i / fps` seconds, so the same frame always looks the same however the
+/// render is split up.
+#[derive(Debug, Clone, Copy)]
+pub struct Timing {
+    pub fps: f64,
+    /// Seconds.
+    pub duration: f64,
+}
+
+#[derive(Deserialize)]
+struct PathFile {
+    #[serde(rename = "keyframe")]
+    keyframes: Vec<KeyframeDescription>,
+}
+
+#[derive(Deserialize)]
+struct KeyframeDescription {
+    time: Option<f64>,
+    #[serde(with = "crate::serde_glm::dvec3")]
+    position: glm::DVec3,
+    #[serde(with = "crate::serde_glm::vec3")]
+    forward: glm::Vec3,
+    #[serde(default = "default_up", with = "crate::serde_glm::vec3")]
+    up: glm::Vec3,
+}
+
+fn default_up() -> glm::Vec3 {
+    glm::vec3(0.0, 1.0, 0.0)
+}
+
+impl CameraPath {
+    /// Loads a path. Keyframes without times are spread over `duration`
+    /// seconds, which is then needed.
+    pub fn load<P: AsRef<Path>>(path: P, duration: Option<f64>) -> Result<Self, Box<dyn Error>> {
+        let file: PathFile = toml::from_str(&fs::read_to_string(path)?)?;
+        let count = file.keyframes.len();
+        if count == 0 {
+            return Err("the camera path has no keyframes".into());
+        }
+        let timed = file.keyframes.iter().filter(|k| k.time.is_some()).count();
+        let spacing = if timed == count {
+            0.0
+        } else if timed == 0 {
+            let duration = duration.ok_or("keyframes without times need a duration")?;
+            duration / (count - 1).max(1) as f64
+        } else {
+            return Err("either all keyframes need a time or none".into());
+        };
+
+        let keyframes: Vec<_> = file
+            .keyframes
+            .into_iter()
+            .enumerate()
+            .map(|(i, keyframe)| Keyframe {
+                time: keyframe.time.unwrap_or(i as f64 * spacing),
+                state: CameraState::looking_along(
+                    keyframe.position,
+                    glm::to_dvec3(keyframe.forward),
+                    glm::to_dvec3(keyframe.up),
+                ),
+            })
+            .collect();
+        if keyframes[0].time < 0.0 {
+            return Err("keyframe times must not be negative".into());
+        }
+        Ok(Self {
+            track: KeyframeTrack::new(&keyframes)?,
+        })
+    }
+
+    /// The time of the last keyframe in seconds.
+    pub fn duration(&self) -> f64 {
+        self.track.duration()
+    }
+
+    /// Moves the camera of `scene` to where the path has it at `time`
+    /// seconds. Before the first keyframe and after the last the camera
+    /// stays there.
+    pub fn apply(&self, scene: &mut Scene, time: f64) {
+        scene.camera.set_state(self.track.sample(time));
+        scene.settle();
+    }
+}
+
+impl Timing {
+    pub fn frame_count(&self) -> usize {
+        ((self.duration * self.fps).round() as usize).max(1)
+    }
+
+    /// Seconds from the start of the animation at frame `i`.
+    pub fn frame_time(&self, i: usize) -> f64 {
+        i as f64 / self.fps
+    }
+}
+
+/// Renders the frames of an animation along `path` into `dir` as
+/// `frame-00000.png` and so on, with `render`. Frames already in `dir` are
+/// skipped, so an interrupted render picks up where it stopped. Each frame
+/// is written under another name first and renamed once complete, so a frame
+/// cut off half way is never taken for a finished one.
+pub fn render_frames(
+    scene: &Scene,
+    path: &CameraPath,
+    timing: Timing,
+    dir: &Path,
+    mut render: impl FnMut(&Scene) -> Result<Image, Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(dir)?;
+    let count = timing.frame_count();
+    let mut scene = scene.clone();
+    for i in 0..count {
+        let frame_path = dir.join(format!("frame-{i:05}.png"));
+        if frame_path.exists() {
+            eprintln!("Frame {}/{count} already rendered", i + 1);
+            continue;
+        }
+        path.apply(&mut scene, timing.frame_time(i));
+        let partial_path = frame_path.with_extension("png.part");
+        render(&scene)?.save_png(&partial_path)?;
+        fs::rename(&partial_path, &frame_path)?;
+        eprintln!("Frame {}/{count} rendered", i + 1);
+    }
+    Ok(())
+}
@@ -1,14 +1,15 @@
 use std::error::Error;
 use std::num::NonZeroU32;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use winit::application::ApplicationHandler;
-use winit::dpi::PhysicalPosition;
+use winit::dpi::{PhysicalPosition, PhysicalSize};
 use winit::event::{ElementState, KeyEvent, MouseButton, WindowEvent};
-use winit::event_loop::ActiveEventLoop;
+use winit::event_loop::{ActiveEventLoop, ControlFlow};
 use winit::keyboard::{Key, NamedKey};
 use winit::raw_window_handle::HasWindowHandle;
-use winit::window::{Window, WindowAttributes};
+use winit::window::{Fullscreen, Window, WindowAttributes};
 
 use glutin::config::{Config, ConfigTemplateBuilder, GetGlConfig};
 use glutin::context::{
@@ -20,9 +21,12 @@ use glutin::surface::{Surface, SwapInterval, WindowSurface};
 
 use glutin_winit::{DisplayBuilder, GlWindow};
 
+use crate::quaternion::Quaternion;
 use crate::renderer::*;
-use crate::scene::Scene;
-use crate::shader::mandelbulb::mandelbulb;
+use crate::scene::{RenderSettings, Scene};
+use crate::screenshot;
+use crate::shader::source::ShaderWatcher;
+use crate::track::CameraState;
 
 pub mod gl {
     #![allow(clippy::all)]
@@ -31,22 +35,32 @@ pub mod gl {
     pub use Gles2 as Gl;
 }
 
-pub fn run_app(event_loop: winit::event_loop::EventLoop<()>) -> Result<(), Box<dyn Error>> {
+/// How the window is opened.
+#[derive(Debug, Clone, Copy)]
+pub struct WindowOptions {
+    /// Size of the window's contents in physical pixels. The platform picks
+    /// one if it is `None`.
+    pub size: Option<(u32, u32)>,
+    /// Covers the current monitor instead of opening a window on it.
+    pub fullscreen: bool,
+    /// Waits for the display to refresh before showing each frame.
+    pub vsync: bool,
+}
+
+pub fn run_app(
+    event_loop: winit::event_loop::EventLoop<()>,
+    scene: Scene,
+    window: WindowOptions,
+    shader_watcher: Option<ShaderWatcher>,
+) -> Result<(), Box<dyn Error>> {
     // The template will match only the configurations supporting rendering
     // to windows.
-    //
-    // XXX We force transparency only on macOS, given that EGL on X11 doesn't
-    // have it, but we still want to show window. The macOS situation is like
-    // that, because we can query only one config at a time on it, but all
-    // normal platforms will return multiple configs, so we can find the config
-    // with transparency ourselves inside the `reduce`.
-    let template = ConfigTemplateBuilder::new()
-        .with_alpha_size(8)
-        .with_transparency(cfg!(cgl_backend));
-
-    let display_builder = DisplayBuilder::new().with_window_attributes(Some(window_attributes()));
-
-    let mut app = App::new(template, display_builder);
+    let template = ConfigTemplateBuilder::new().with_alpha_size(8);
+
+    let display_builder =
+        DisplayBuilder::new().with_window_attributes(Some(window_attributes(&window)));
+
+    let mut app = App::new(template, display_builder, scene, window, shader_watcher);
     event_loop.run_app(&mut app)?;
 
     app.exit_state
@@ -61,10 +75,68 @@ struct App {
     gl_display: GlDisplayCreationState,
     exit_state: Result<(), Box<dyn Error>>,
     scene: Scene,
+    window: WindowOptions,
+    shader_watcher: Option<ShaderWatcher>,
+    /// When the user last moved the camera or changed the scene.
+    last_change: Instant,
+    resolution: ResolutionScale,
+}
+
+/// How long the view has to stay the same before frames are drawn at full
+/// resolution again.
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+
+/// The fraction of the window size frames are drawn at while the view
+/// changes, adjusted from the time between frames so that they come at about
+/// `RenderSettings::target_fps`.
+struct ResolutionScale {
+    scale: f32,
+    last_frame: Option<Instant>,
+}
+
+impl ResolutionScale {
+    fn new() -> Self {
+        Self {
+            scale: 1.0,
+            last_frame: None,
+        }
+    }
+
+    /// The scale for the next frame while the view changes.
+    fn changing(&mut self, settings: &RenderSettings) -> f32 {
+        let now = Instant::now();
+        if let Some(last_frame) = self.last_frame.replace(now) {
+            let frame_time = (now - last_frame).as_secs_f32();
+            // The time a frame takes goes with the number of pixels, which
+            // goes with the square of the scale. Small differences are left
+            // alone so that the resolution does not flicker.
+            let ratio = (1.0 / (settings.target_fps * frame_time)).sqrt();
+            if !(0.9..1.1).contains(&ratio) {
+                self.scale *= ratio.clamp(0.5, 1.25);
+            }
+        }
+        self.scale = self
+            .scale
+            .clamp(settings.min_resolution_scale.min(1.0), 1.0);
+        self.scale
+    }
+
+    /// The scale for the next frame once the view has settled. The scale
+    /// from before is kept for the next change.
+    fn settled(&mut self) -> f32 {
+        self.last_frame = None;
+        1.0
+    }
 }
 
 impl App {
-    fn new(template: ConfigTemplateBuilder, display_builder: DisplayBuilder) -> Self {
+    fn new(
+        template: ConfigTemplateBuilder,
+        display_builder: DisplayBuilder,
+        scene: Scene,
+        window: WindowOptions,
+        shader_watcher: Option<ShaderWatcher>,
+    ) -> Self {
         Self {
             template,
             gl_display: GlDisplayCreationState::Builder(display_builder),
@@ -72,7 +144,11 @@ impl App {
             gl_context: None,
             state: None,
             renderer: None,
-            scene: Scene::init(),
+            scene,
+            window,
+            shader_watcher,
+            last_change: Instant::now(),
+            resolution: ResolutionScale::new(),
         }
     }
 
@@ -83,6 +159,63 @@ impl App {
             .as_millis()
     }
 
+    /// Renders the current view at window size with the scene's number of
+    /// samples, like the window shows it once the view stays still, and saves
+    /// it with its sidecar into the working directory.
+    fn save_screenshot(&self) {
+        let (Some(AppState { window, .. }), Some(renderer)) =
+            (self.state.as_ref(), self.renderer.as_ref())
+        else {
+            return;
+        };
+        let size = window.inner_size();
+        let name = format!("screenshot-{}", self.get_time());
+        let render = &self.scene.render;
+        let result = renderer
+            .render_tiled(
+                &self.scene,
+                size.width,
+                size.height,
+                render.tile_size,
+                render.samples,
+                |_, _| {},
+            )
+            .and_then(|image| screenshot::save(Path::new("."), &name, &image, &self.scene));
+        match result {
+            Ok(path) => println!("Saved screenshot to {}", path.display()),
+            Err(err) => eprintln!("Error saving screenshot: {err}"),
+        }
+    }
+
+    /// Recompiles the fragment shader if its files changed on disk. On failure
+    /// the previous program keeps running.
+    fn reload_shaders(&mut self) {
+        let (Some(watcher), Some(renderer)) =
+            (self.shader_watcher.as_mut(), self.renderer.as_mut())
+        else {
+            return;
+        };
+        let result = match watcher.poll() {
+            None => return,
+            Some(Err(err)) => Err(err.to_string()),
+            Some(Ok(source)) => renderer
+                .reload_fragment_shader(&source)
+                .map_err(|err| err.to_string()),
+        };
+        match result {
+            Ok(()) => println!("Reloaded shaders."),
+            Err(err) => eprintln!("Shader reload failed, keeping the previous program: {err}"),
+        }
+        self.request_redraw();
+    }
+
+    /// Redraws after the user changed the view. Until it settles, frames are
+    /// drawn at a lower resolution if they are slow.
+    fn view_changed(&mut self) {
+        self.last_change = Instant::now();
+        self.request_redraw();
+    }
+
     fn request_redraw(&self) {
         if let Some(AppState {
             gl_surface: _,
@@ -129,7 +262,11 @@ impl ApplicationHandler for App {
                 println!("Recreating window in `resumed`");
                 // Pick the config which we already use for the context.
                 let gl_config = self.gl_context.as_ref().unwrap().config();
-                match glutin_winit::finalize_window(event_loop, window_attributes(), &gl_config) {
+                match glutin_winit::finalize_window(
+                    event_loop,
+                    window_attributes(&self.window),
+                    &gl_config,
+                ) {
                     Ok(window) => (window, gl_config),
                     Err(err) => {
                         self.exit_state = Err(err.into());
@@ -156,13 +293,24 @@ impl ApplicationHandler for App {
         let gl_context = self.gl_context.as_ref().unwrap();
         gl_context.make_current(&gl_surface).unwrap();
 
-        self.renderer
-            .get_or_insert_with(|| Renderer::new(&gl_config.display()));
+        if self.renderer.is_none() {
+            match Renderer::new(&gl_config.display()) {
+                Ok(renderer) => self.renderer = Some(renderer),
+                Err(err) => {
+                    self.exit_state = Err(err.into());
+                    event_loop.exit();
+                    return;
+                }
+            }
+        }
 
         // Try setting vsync.
-        if let Err(res) = gl_surface
-            .set_swap_interval(gl_context, SwapInterval::Wait(NonZeroU32::new(1).unwrap()))
-        {
+        let interval = if self.window.vsync {
+            SwapInterval::Wait(NonZeroU32::new(1).unwrap())
+        } else {
+            SwapInterval::DontWait
+        };
+        if let Err(res) = gl_surface.set_swap_interval(gl_context, interval) {
             eprintln!("Error setting vsync: {res:?}");
         }
 
@@ -236,7 +384,19 @@ impl ApplicationHandler for App {
                 ..
             } => event_loop.exit(),
             WindowEvent::KeyboardInput {
-                event: KeyEvent { logical_key, .. },
+                event:
+                    KeyEvent {
+                        logical_key: Key::Character(ref k),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } if k == "p" => self.save_screenshot(),
+            WindowEvent::KeyboardInput {
+                event: KeyEvent {
+                    logical_key, state, ..
+                },
                 ..
             } => {
                 let mut act = true;
@@ -244,11 +404,20 @@ impl ApplicationHandler for App {
                 match logical_key {
                     Key::Character(k) if k == "r" => {
                         self.scene.camera.animate_between(
-                            glm::vec3(0.0, 0.0, 2.0),
-                            glm::vec3(0.0, 0.0, -1.0),
+                            CameraState {
+                                position: glm::dvec3(0.0, 0.0, 2.0),
+                                orientation: Quaternion::IDENTITY,
+                                focal_distance: 1.0,
+                            },
                             1000,
                         );
                     }
+                    Key::Character(k) if k == "q" && state == ElementState::Pressed => {
+                        self.scene.camera.roll(-0.05)
+                    }
+                    Key::Character(k) if k == "e" && state == ElementState::Pressed => {
+                        self.scene.camera.roll(0.05)
+                    }
                     Key::Character(k) if k == "w" => {
                         self.scene.camera.translate_local(0., 0., -speed)
                     }
@@ -261,10 +430,34 @@ impl ApplicationHandler for App {
                     Key::Character(k) if k == "d" => {
                         self.scene.camera.translate_local(speed, 0., 0.)
                     }
+                    Key::Character(k) if k == "f" && state == ElementState::Pressed => {
+                        let fractal = &mut self.scene.fractal;
+                        fractal.formula = fractal.formula.next();
+                        println!("Switched to {:?}", fractal.formula);
+                    }
+                    Key::Character(k) if k == "z" && state == ElementState::Pressed => {
+                        let render = &mut self.scene.render;
+                        render.deep_zoom = !render.deep_zoom;
+                        println!("Deep zoom {}", if render.deep_zoom { "on" } else { "off" });
+                    }
+                    Key::Character(k)
+                        if ["1", "2", "3", "4"].contains(&k.as_str())
+                            && state == ElementState::Pressed =>
+                    {
+                        let shading = &mut self.scene.shading;
+                        let (name, enabled) = match k.as_str() {
+                            "1" => ("ambient", &mut shading.ambient),
+                            "2" => ("diffuse", &mut shading.diffuse),
+                            "3" => ("specular", &mut shading.specular),
+                            _ => ("rim", &mut shading.rim),
+                        };
+                        *enabled = !*enabled;
+                        println!("Turned {name} {}", if *enabled { "on" } else { "off" });
+                    }
                     _ => act = false,
                 };
                 if act {
-                    self.request_redraw();
+                    self.view_changed();
                 }
             }
             WindowEvent::MouseInput {
@@ -278,7 +471,10 @@ impl ApplicationHandler for App {
                 }
             }
             WindowEvent::MouseWheel { delta, .. } => {
-                let dist = mandelbulb(&self.scene.camera.position, 4.0, 0.0);
+                let dist = self
+                    .scene
+                    .fractal
+                    .zoom_distance(&glm::to_vec3(self.scene.camera.position));
                 match delta {
                     winit::event::MouseScrollDelta::LineDelta(_, dy) => {
                         self.scene.camera.zoom(dy, dist);
@@ -287,7 +483,7 @@ impl ApplicationHandler for App {
                         self.scene.camera.zoom(x as f32, dist);
                     }
                 };
-                self.request_redraw();
+                self.view_changed();
             }
             WindowEvent::CursorMoved { position, .. } => {
                 let new_mouse = glm::vec2(position.x as f32, position.y as f32);
@@ -298,27 +494,49 @@ impl ApplicationHandler for App {
                 if let Some(mouse) = self.scene.mouse {
                     let delta = new_mouse - mouse;
                     self.scene.camera.orbit_controls(delta.x, delta.y);
-                    self.request_redraw();
+                    self.view_changed();
                 }
 
                 self.scene.mouse.replace(new_mouse);
             }
             WindowEvent::RedrawRequested => {
-                if let Some(AppState { gl_surface, .. }) = self.state.as_ref() {
+                let now = self.get_time();
+                if let Some(AppState { gl_surface, window }) = self.state.as_ref() {
                     let gl_context = self.gl_context.as_ref().unwrap();
-                    let renderer = self.renderer.as_ref().unwrap();
-                    if self.scene.should_update() {
-                        self.scene.update_time(self.get_time());
-                        self.request_redraw();
+                    let renderer = self.renderer.as_mut().unwrap();
+                    let moving = self.scene.should_update();
+                    if moving {
+                        self.scene.update_time(now);
                     }
-                    renderer.draw(&self.scene.camera, &self.scene.light);
+                    let changing = moving || self.last_change.elapsed() < SETTLE_TIME;
+                    let scale = if changing {
+                        self.resolution.changing(&self.scene.render)
+                    } else {
+                        self.resolution.settled()
+                    };
+                    let samples =
+                        renderer.draw_accumulated(&self.scene, self.scene.render.samples, scale);
                     gl_surface.swap_buffers(gl_context).unwrap();
+                    // Keep drawing until the view has settled, then keep
+                    // adding samples while it stays the same.
+                    if changing || samples < self.scene.render.samples {
+                        window.request_redraw();
+                    }
                 }
             }
             _ => (),
         }
     }
 
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        if self.shader_watcher.is_some() {
+            self.reload_shaders();
+            event_loop.set_control_flow(ControlFlow::WaitUntil(
+                Instant::now() + Duration::from_millis(250),
+            ));
+        }
+    }
+
     fn exiting(&mut self, _event_loop: &ActiveEventLoop) {
         // NOTE: The handling below is only needed due to nvidia on Wayland to not crash
         // on exit due to nvidia driver touching the Wayland display from on
@@ -375,10 +593,16 @@ fn create_gl_context(window: &Window, gl_config: &Config) -> NotCurrentContext {
     }
 }
 
-fn window_attributes() -> WindowAttributes {
-    Window::default_attributes()
-        .with_transparent(true)
-        .with_title("Boraini's Raymarcher (press Escape to exit)")
+fn window_attributes(options: &WindowOptions) -> WindowAttributes {
+    let mut attributes =
+        Window::default_attributes().with_title("Boraini's Raymarcher (press Escape to exit)");
+    if let Some((width, height)) = options.size {
+        attributes = attributes.with_inner_size(PhysicalSize::new(width, height));
+    }
+    if options.fullscreen {
+        attributes = attributes.with_fullscreen(Some(Fullscreen::Borderless(None)));
+    }
+    attributes
 }
 
 enum GlDisplayCreationState {
@@ -400,10 +624,7 @@ struct AppState {
 pub fn gl_config_picker(configs: Box<dyn Iterator<Item = Config> + '_>) -> Config {
     configs
         .reduce(|accum, config| {
-            let transparency_check = config.supports_transparency().unwrap_or(false)
-                & !accum.supports_transparency().unwrap_or(false);
-
-            if transparency_check || config.num_samples() > accum.num_samples() {
+            if config.num_samples() > accum.num_samples() {
                 config
             } else {
                 accum
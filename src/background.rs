@@ -0,0 +1,304 @@
+use std::{
+    error::Error,
+    f32::consts::PI,
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use glm::{acos, clamp, dot, max, mix, pow, smoothstep, vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::{hdr::HdrImage, light::SunLight};
+
+/// Size of the irradiance map computed for an environment, and of the copy of
+/// the environment it is integrated from.
+pub const IRRADIANCE_SIZE: (u32, u32) = (32, 16);
+const IRRADIANCE_SOURCE_SIZE: (u32, u32) = (64, 32);
+
+/// What rays that miss the fractal see. It is also the light the ambient and
+/// reflection terms in `Shading` pick up. The discriminants match the
+/// `BACKGROUND_*` defines in the shader.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Background {
+    Solid {
+        #[serde(with = "crate::serde_glm::vec3")]
+        color: glm::Vec3,
+    },
+    /// Blends from `bottom` straight down to `top` straight up.
+    Gradient {
+        #[serde(with = "crate::serde_glm::vec3")]
+        bottom: glm::Vec3,
+        #[serde(with = "crate::serde_glm::vec3")]
+        top: glm::Vec3,
+    },
+    /// A sky fading from `horizon` to `zenith` above and to `ground` below,
+    /// with the sun drawn where the scene's `SunLight` comes from.
+    Sky {
+        #[serde(with = "crate::serde_glm::vec3")]
+        zenith: glm::Vec3,
+        #[serde(with = "crate::serde_glm::vec3")]
+        horizon: glm::Vec3,
+        #[serde(with = "crate::serde_glm::vec3")]
+        ground: glm::Vec3,
+    },
+    /// An equirectangular Radiance `.hdr` image, relative to the scene file.
+    /// Its pixels are scaled by `exposure`.
+    Environment {
+        path: PathBuf,
+        exposure: f32,
+        #[serde(skip)]
+        map: Option<Arc<EnvironmentMap>>,
+    },
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Background::Sky {
+            zenith: vec3(0.25, 0.45, 0.85),
+            horizon: vec3(0.75, 0.85, 0.95),
+            ground: vec3(0.3, 0.28, 0.25),
+        }
+    }
+}
+
+// Cosines of the angles from the sun's centre where its disc starts to fade
+// and where it ends.
+const SUN_INNER: f32 = 0.9996;
+const SUN_OUTER: f32 = 0.9992;
+
+impl Background {
+    /// Loads the image of an environment background, with `dir` the
+    /// directory of the scene file. The path is made absolute, so that the
+    /// scene still finds the image when it is saved somewhere else.
+    pub fn load(&mut self, dir: &Path) -> Result<(), Box<dyn Error>> {
+        if let Background::Environment { path, map, .. } = self {
+            *path = std::path::absolute(dir.join(&*path))?;
+            let image = HdrImage::load(&*path)
+                .map_err(|err| format!("failed to load environment {}: {err}", path.display()))?;
+            *map = Some(Arc::new(EnvironmentMap::new(image)));
+        }
+        Ok(())
+    }
+
+    /// The background as the `background_type`, `background_top`,
+    /// `background_horizon`, `background_bottom` and `exposure` uniforms.
+    pub fn uniforms(&self) -> (i32, glm::Vec3, glm::Vec3, glm::Vec3, f32) {
+        match *self {
+            Background::Solid { color } => (0, color, color, color, 1.0),
+            Background::Gradient { bottom, top } => {
+                (1, top, mix(bottom, top, vec3(0.5, 0.5, 0.5)), bottom, 1.0)
+            }
+            Background::Sky {
+                zenith,
+                horizon,
+                ground,
+            } => (2, zenith, horizon, ground, 1.0),
+            Background::Environment { exposure, .. } => {
+                let black = vec3(0.0, 0.0, 0.0);
+                (3, black, black, black, exposure)
+            }
+        }
+    }
+
+    pub fn environment(&self) -> Option<&Arc<EnvironmentMap>> {
+        match self {
+            Background::Environment { map, .. } => map.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Port of `background` in `background.glsl`.
+    pub fn color(&self, dir: glm::Vec3, light: &SunLight) -> glm::Vec3 {
+        if let Background::Environment { exposure, map, .. } = self {
+            return map
+                .as_ref()
+                .map_or(vec3(0.0, 0.0, 0.0), |map| map.sample(dir) * *exposure);
+        }
+        let mut color = self.sky_gradient(dir);
+        if let Background::Sky { .. } = self {
+            let s = max(dot(dir, -light.direction), 0.0);
+            color =
+                color + light.color * (0.25 * pow(s, 32.0) + smoothstep(SUN_OUTER, SUN_INNER, s));
+        }
+        color
+    }
+
+    /// Port of `fog_colour` in `background.glsl`.
+    pub fn fog_colour(&self, dir: glm::Vec3) -> glm::Vec3 {
+        if let Background::Environment { exposure, map, .. } = self {
+            return map.as_ref().map_or(vec3(0.0, 0.0, 0.0), |map| {
+                map.sample_irradiance(dir) * *exposure
+            });
+        }
+        self.sky_gradient(dir)
+    }
+
+    /// Port of `ambient_light` in `background.glsl`.
+    pub fn ambient_light(&self, n: glm::Vec3) -> glm::Vec3 {
+        if let Background::Environment { exposure, map, .. } = self {
+            return map.as_ref().map_or(vec3(0.0, 0.0, 0.0), |map| {
+                map.sample_irradiance(n) * *exposure
+            });
+        }
+        match *self {
+            Background::Gradient { bottom, top } => {
+                let t = 0.5 + n.y / 3.0;
+                mix(bottom, top, vec3(t, t, t))
+            }
+            _ => self.sky_gradient(n),
+        }
+    }
+
+    fn sky_gradient(&self, dir: glm::Vec3) -> glm::Vec3 {
+        let (_, top, horizon, bottom, _) = self.uniforms();
+        match self {
+            Background::Gradient { .. } => {
+                let t = dir.y * 0.5 + 0.5;
+                mix(bottom, top, vec3(t, t, t))
+            }
+            Background::Sky { .. } => {
+                let (to, t) = if dir.y >= 0.0 {
+                    (top, dir.y.sqrt())
+                } else {
+                    (bottom, (-dir.y).sqrt())
+                };
+                mix(horizon, to, vec3(t, t, t))
+            }
+            _ => top,
+        }
+    }
+}
+
+/// An equirectangular environment and its irradiance, the cosine weighted
+/// average of the environment over each hemisphere, which lights diffuse
+/// surfaces. Both are sampled the way the shader's linearly filtered textures
+/// are, wrapping around horizontally.
+pub struct EnvironmentMap {
+    pub image: HdrImage,
+    pub irradiance: HdrImage,
+}
+
+impl fmt::Debug for EnvironmentMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "EnvironmentMap({}x{})",
+            self.image.width, self.image.height
+        )
+    }
+}
+
+impl EnvironmentMap {
+    pub fn new(image: HdrImage) -> Self {
+        let irradiance = irradiance(&downsample(&image, IRRADIANCE_SOURCE_SIZE));
+        Self { image, irradiance }
+    }
+
+    pub fn sample(&self, dir: glm::Vec3) -> glm::Vec3 {
+        sample(&self.image, equirectangular(dir))
+    }
+
+    pub fn sample_irradiance(&self, n: glm::Vec3) -> glm::Vec3 {
+        sample(&self.irradiance, equirectangular(n))
+    }
+}
+
+/// Port of `equirectangular` in `background.glsl`, the texture coordinates
+/// of a unit direction. The top row of the image is straight up.
+fn equirectangular(dir: glm::Vec3) -> glm::Vec2 {
+    glm::vec2(
+        dir.z.atan2(dir.x) / (2.0 * PI) + 0.5,
+        acos(clamp(dir.y, -1.0, 1.0)) / PI,
+    )
+}
+
+/// The unit direction at the centre of pixel `(x, y)`.
+fn direction(image: &HdrImage, x: u32, y: u32) -> glm::Vec3 {
+    let phi = ((x as f32 + 0.5) / image.width as f32 - 0.5) * 2.0 * PI;
+    let theta = (y as f32 + 0.5) / image.height as f32 * PI;
+    vec3(
+        theta.sin() * phi.cos(),
+        theta.cos(),
+        theta.sin() * phi.sin(),
+    )
+}
+
+fn sample(image: &HdrImage, uv: glm::Vec2) -> glm::Vec3 {
+    let texel = |x: i64, y: i64| {
+        let x = x.rem_euclid(image.width as i64);
+        let y = y.clamp(0, image.height as i64 - 1);
+        image.pixels[(y * image.width as i64 + x) as usize]
+    };
+    let x = uv.x * image.width as f32 - 0.5;
+    let y = uv.y * image.height as f32 - 0.5;
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let (x0, y0) = (x0 as i64, y0 as i64);
+    let top = mix(texel(x0, y0), texel(x0 + 1, y0), vec3(fx, fx, fx));
+    let bottom = mix(texel(x0, y0 + 1), texel(x0 + 1, y0 + 1), vec3(fx, fx, fx));
+    mix(top, bottom, vec3(fy, fy, fy))
+}
+
+/// Averages blocks of pixels down to `size`.
+fn downsample(image: &HdrImage, (width, height): (u32, u32)) -> HdrImage {
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        let rows = (y * image.height / height)..((y + 1) * image.height / height).max(1);
+        for x in 0..width {
+            let cols = (x * image.width / width)..((x + 1) * image.width / width).max(1);
+            let mut sum = vec3(0.0, 0.0, 0.0);
+            let mut count = 0;
+            for row in rows.clone() {
+                for col in cols.clone() {
+                    sum = sum + image.pixels[(row * image.width + col) as usize];
+                    count += 1;
+                }
+            }
+            pixels.push(sum / count.max(1) as f32);
+        }
+    }
+    HdrImage {
+        width,
+        height,
+        pixels,
+    }
+}
+
+/// Integrates `image` against a clamped cosine around the direction of each
+/// pixel of an `IRRADIANCE_SIZE` map, divided by pi so that a uniform
+/// environment gives back its own colour.
+fn irradiance(image: &HdrImage) -> HdrImage {
+    let texels: Vec<(glm::Vec3, glm::Vec3)> = (0..image.height)
+        .flat_map(|y| (0..image.width).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let dir = direction(image, x, y);
+            let theta = (y as f32 + 0.5) / image.height as f32 * PI;
+            let solid_angle =
+                (2.0 * PI / image.width as f32) * (PI / image.height as f32) * theta.sin();
+            (
+                dir,
+                image.pixels[(y * image.width + x) as usize] * solid_angle,
+            )
+        })
+        .collect();
+
+    let (width, height) = IRRADIANCE_SIZE;
+    let mut map = HdrImage {
+        width,
+        height,
+        pixels: Vec::with_capacity((width * height) as usize),
+    };
+    for y in 0..height {
+        for x in 0..width {
+            let n = direction(&map, x, y);
+            let mut sum = vec3(0.0, 0.0, 0.0);
+            for &(dir, radiance) in &texels {
+                sum = sum + radiance * max(dot(n, dir), 0.0);
+            }
+            map.pixels.push(sum / PI);
+        }
+    }
+    map
+}
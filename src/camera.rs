@@ -1,16 +1,25 @@
 use core::f32;
 
+use crate::quaternion::Quaternion;
 use crate::three_d::LocalToGlobal;
+use crate::track::CameraState;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Camera {
-    pub position: glm::Vec3,
-    pub forward: glm::Vec3,
+    /// Kept in double precision so that moving around a deep zoom, where the
+    /// focal distance is far below the spacing of `f32` positions, still
+    /// works. See `split_position` for how it reaches the shader.
+    pub position: glm::DVec3,
+    /// Turns -z to the direction the camera looks in and y to the top of
+    /// the screen. Unlike sides worked out from a fixed up vector, it stays
+    /// defined looking straight up or down, and it can roll.
+    pub orientation: Quaternion,
+    /// How far ahead the focus is, which the camera orbits around. It sets
+    /// the scale of the view: movements and the stop distance follow it.
+    pub focal_distance: f64,
     pub fov: f32,
-    prev_position: glm::Vec3,
-    next_position: glm::Vec3,
-    prev_forward: glm::Vec3,
-    next_forward: glm::Vec3,
+    prev: CameraState,
+    next: CameraState,
     t: u128,
     t_start: u128,
     t_end: u128,
@@ -18,6 +27,39 @@ pub struct Camera {
     update_flag: bool,
 }
 
+/// Interpolates the corner rays the way the rasteriser does for the
+/// `TRIANGLE_STRIP` quad drawn by `Renderer`, so a pixel gets the same
+/// (unnormalised) `ray_direction` as in the fragment shader. `u` and `v` go
+/// from 0 to 1 left to right and bottom to top.
+pub fn ray_direction(corners: &[glm::Vec3; 4], u: f32, v: f32) -> glm::Vec3 {
+    let [c00, c01, c10, c11] = *corners;
+    if u + v <= 1.0 {
+        c00 + (c10 - c00) * u + (c01 - c00) * v
+    } else {
+        c11 + (c01 - c11) * (1.0 - u) + (c10 - c11) * (1.0 - v)
+    }
+}
+
+/// The subpixel offset of sample `i`, from the Halton sequence in bases 2 and
+/// 3, which covers the pixel evenly however many samples are taken. The first
+/// sample is at the centre of the pixel.
+pub fn sample_offset(i: u32) -> glm::Vec2 {
+    if i == 0 {
+        return glm::vec2(0.0, 0.0);
+    }
+    let halton = |mut i: u32, base: u32| {
+        let mut fraction = 1.0;
+        let mut value = 0.0;
+        while i > 0 {
+            fraction /= base as f32;
+            value += fraction * (i % base) as f32;
+            i /= base;
+        }
+        value
+    };
+    glm::vec2(halton(i, 2) - 0.5, halton(i, 3) - 0.5)
+}
+
 fn easing(t: u128, a: u128, b: u128) -> f32 {
     let t = ((t - a) as f32) / ((b - a) as f32);
     if t < 0.0 {
@@ -31,13 +73,17 @@ fn easing(t: u128, a: u128, b: u128) -> f32 {
 
 impl Camera {
     pub fn new() -> Self {
+        let state = CameraState {
+            position: glm::dvec3(0.0, 0.0, 0.0),
+            orientation: Quaternion::IDENTITY,
+            focal_distance: 1.0,
+        };
         Self {
-            position: glm::vec3(0.0, 0.0, 0.0),
-            forward: glm::vec3(0.0, 0.0, -1.0),
-            prev_position: glm::vec3(0.0, 0.0, 0.0),
-            prev_forward: glm::vec3(0.0, 0.0, -1.0),
-            next_position: glm::vec3(0.0, 0.0, 0.0),
-            next_forward: glm::vec3(0.0, 0.0, -1.0),
+            position: state.position,
+            orientation: state.orientation,
+            focal_distance: state.focal_distance,
+            prev: state,
+            next: state,
             t: 0,
             t_start: 0,
             t_end: 0,
@@ -51,20 +97,47 @@ impl Camera {
         self.t = t;
         let fac = easing(t, self.t_start, self.t_end);
         if fac <= 0.0 {
-            self.position = self.prev_position;
-            self.forward = self.prev_forward;
+            self.set_current(self.prev);
             return;
         }
         if fac >= 1.0 {
-            self.position = self.next_position;
-            self.forward = self.next_forward;
+            self.set_current(self.next);
+            self.update_flag = false;
             return;
         }
-        self.position = glm::mix_s(self.prev_position, self.next_position, fac);
-        self.forward = glm::mix_s(self.prev_forward, self.next_forward, fac);
+        self.set_current(self.prev.interpolate(&self.next, fac as f64));
         self.update_flag = false;
     }
 
+    pub fn state(&self) -> CameraState {
+        CameraState {
+            position: self.position,
+            orientation: self.orientation,
+            focal_distance: self.focal_distance,
+        }
+    }
+
+    fn set_current(&mut self, state: CameraState) {
+        self.position = state.position;
+        self.orientation = state.orientation;
+        self.focal_distance = state.focal_distance;
+    }
+
+    /// From the camera to the focus.
+    pub fn forward(&self) -> glm::Vec3 {
+        glm::to_vec3(self.state().forward())
+    }
+
+    /// The unit vector towards the top of the screen.
+    pub fn up(&self) -> glm::Vec3 {
+        glm::to_vec3(self.orientation.rotate(glm::dvec3(0.0, 1.0, 0.0)))
+    }
+
+    /// The unit vector towards the right of the screen.
+    fn right(&self) -> glm::Vec3 {
+        glm::to_vec3(self.orientation.rotate(glm::dvec3(1.0, 0.0, 0.0)))
+    }
+
     pub fn set_aspect(&mut self, w: f32, h: f32) {
         if w == 0.0 || h == 0.0 {
             self.aspect = 1.0;
@@ -73,41 +146,33 @@ impl Camera {
         }
     }
 
-    pub fn set_position_and_forward(&mut self, next_position: glm::Vec3, next_forward: glm::Vec3) {
-        self.next_position = next_position;
-        self.next_forward = next_forward;
+    pub fn set_state(&mut self, next: CameraState) {
+        self.next = next;
         self.t_start = 0;
         self.t_end = 1;
         self.t = 2;
     }
 
-    pub fn animate_between(
-        &mut self,
-        next_position: glm::Vec3,
-        next_forward: glm::Vec3,
-        duration: u128,
-    ) {
+    pub fn animate_between(&mut self, next: CameraState, duration: u128) {
         self.update_time(self.t);
-        self.prev_position = self.position;
-        self.next_position = next_position;
-        self.prev_forward = self.forward;
-        self.next_forward = next_forward;
+        self.prev = self.state();
+        self.next = next;
         self.t_start = self.t;
         self.t_end = self.t + duration;
     }
 
     pub fn get_corners(&self) -> [glm::Vec3; 4] {
         let mut dest: [glm::Vec3; 4] = unsafe { std::mem::zeroed() };
-        let foc = glm::length(self.forward);
-        let dx = foc * glm::tan(0.5 * self.fov);
+        let forward = self.forward();
+        let dx = self.focal_distance as f32 * glm::tan(0.5 * self.fov);
         let dy = dx / self.aspect;
-        let right = glm::normalize(glm::cross(self.forward, glm::vec3(0.0, 1.0, 0.0))) * dx;
-        let up = glm::normalize(glm::cross(right, self.forward)) * dy;
+        let right = self.right() * dx;
+        let up = self.up() * dy;
 
         let mut i = 0;
         for x in [-1.0, 1.0] {
             for y in [-1.0, 1.0] {
-                dest[i] = glm::normalize(self.forward + right * x + up * y);
+                dest[i] = glm::normalize(forward + right * x + up * y);
                 i += 1;
             }
         }
@@ -115,72 +180,148 @@ impl Camera {
         dest
     }
 
+    /// The corner rays moved by `offset` pixels of a `width` x `height` image,
+    /// so that every pixel is sampled at another point inside it.
+    pub fn get_jittered_corners(
+        &self,
+        offset: glm::Vec2,
+        width: f32,
+        height: f32,
+    ) -> [glm::Vec3; 4] {
+        let corners = self.get_corners();
+        let right = (corners[2] - corners[0]) / width;
+        let up = (corners[1] - corners[0]) / height;
+        corners.map(|corner| corner + right * offset.x + up * offset.y)
+    }
+
+    /// The position as the nearest `f32` vector and the small remainder, for
+    /// the shader which marches relative to the camera and only adds the two
+    /// to its offset along the ray where it needs absolute positions. Those
+    /// are floats again, so detail below their spacing needs deep zoom mode.
+    pub fn split_position(&self) -> (glm::Vec3, glm::Vec3) {
+        let high = glm::to_vec3(self.position);
+        let low = glm::to_vec3(self.position - glm::to_dvec3(high));
+        (high, low)
+    }
+
     pub fn get_stop_distance(&self) -> f32 {
-        0.00001 * glm::length(self.forward)
+        0.00001 * self.focal_distance as f32
     }
 
     pub fn translate_local(&mut self, dx: f32, dy: f32, dz: f32) {
-        let (d, _) = self.to_global(&glm::vec3(dx, dy, dz), &glm::vec3(0.0, 0.0, 0.0));
-        self.set_position_and_forward(d, self.forward);
+        let (_, d) = self.to_global(&glm::vec3(0.0, 0.0, 0.0), &glm::vec3(dx, dy, dz));
+        self.set_state(CameraState {
+            position: self.position + glm::to_dvec3(d),
+            ..self.state()
+        });
     }
 
+    /// Turns the camera around the focus, about its own up and right axes
+    /// rather than the world's. That leaves no pole for the view to flip
+    /// over, so dragging carries on over the top upside down.
     pub fn orbit_controls(&mut self, dx: f32, dy: f32) {
-        let delta_azimuth = dx / -200.0;
-        let delta_pitch = dy / -200.0;
-
-        let ident = glm::Mat4::new(
-            glm::vec4(1.0, 0.0, 0.0, 0.0),
-            glm::vec4(0.0, 1.0, 0.0, 0.0),
-            glm::vec4(0.0, 0.0, 1.0, 0.0),
-            glm::vec4(0.0, 0.0, 0.0, 1.0),
-        );
-
-        let pitch_axis = glm::cross(self.forward, glm::vec3(0.0, 1.0, 0.0));
-
-        let pitch_matrix = glm::ext::rotate(&ident, delta_pitch, pitch_axis);
-        let azimuth_matrix = glm::ext::rotate(&ident, delta_azimuth, glm::vec3(0.0, 1.0, 0.0));
+        let azimuth = Quaternion::from_axis_angle(glm::dvec3(0.0, 1.0, 0.0), (dx / -200.0) as f64);
+        let pitch = Quaternion::from_axis_angle(glm::dvec3(1.0, 0.0, 0.0), (dy / -200.0) as f64);
+        self.turn_about_focus(self.orientation * azimuth * pitch);
+    }
 
-        let n = azimuth_matrix
-            * pitch_matrix
-            * glm::vec4(self.forward.x, self.forward.y, self.forward.z, 1.0);
-        let next_forward = glm::vec3(n.x, n.y, n.z);
-        let next_position = self.position + self.forward - next_forward;
+    /// Turns the camera clockwise by `angle` radians about the direction it
+    /// looks in, around the focus.
+    pub fn roll(&mut self, angle: f32) {
+        let roll = Quaternion::from_axis_angle(glm::dvec3(0.0, 0.0, -1.0), angle as f64);
+        self.turn_about_focus(self.orientation * roll);
+    }
 
-        self.set_position_and_forward(next_position, next_forward);
+    fn turn_about_focus(&mut self, orientation: Quaternion) {
+        let state = self.state();
+        let next = CameraState {
+            orientation: orientation.normalize(),
+            ..state
+        };
+        self.set_state(CameraState {
+            position: state.position + state.forward() - next.forward(),
+            ..next
+        });
         self.update_flag = true;
     }
 
     pub fn zoom(&mut self, scroll_amount: f32, distance: f32) {
-        let fac = glm::exp(0.3 * scroll_amount);
-        let center_dist = glm::min(distance, glm::length(self.forward));
-        let center_point = self.position + glm::normalize(self.forward) * center_dist;
-        let next_forward = if fac > 1.0 {
-            self.forward * fac
+        let fac = (0.3 * scroll_amount as f64).exp();
+        let state = self.state();
+        let direction = state.forward() / state.focal_distance;
+        let center_dist = (distance as f64).min(state.focal_distance);
+        let center_point = state.position + direction * center_dist;
+        let focal_distance = if fac > 1.0 {
+            state.focal_distance * fac
         } else {
-            glm::normalize(self.forward) * fac * center_dist
+            fac * center_dist
         };
-        let next_position = center_point - next_forward;
 
-        self.set_position_and_forward(next_position, next_forward);
+        self.set_state(CameraState {
+            position: center_point - direction * focal_distance,
+            focal_distance,
+            ..state
+        });
         self.update_flag = true;
     }
 
     pub fn should_update(&self) -> bool {
         self.update_flag || self.t < self.t_end
     }
+
+    /// The time at which the current animation is finished.
+    pub fn end_time(&self) -> u128 {
+        self.t.max(self.t_end)
+    }
 }
 
 impl LocalToGlobal for Camera {
     fn to_global(&self, position: &glm::Vec3, direction: &glm::Vec3) -> (glm::Vec3, glm::Vec3) {
-        let foc = glm::length(self.forward);
-        let dx = foc * glm::tan(0.5 * self.fov);
+        let forward = self.forward();
+        let dx = self.focal_distance as f32 * glm::tan(0.5 * self.fov);
         let dy = dx;
-        let right = glm::normalize(glm::cross(self.forward, glm::vec3(0.0, 1.0, 0.0))) * dx;
-        let up = glm::normalize(glm::cross(right, self.forward)) * dy;
-        let position =
-            self.position + right * position.x + up * position.y - self.forward * position.z;
-        let direction = right * direction.x + up * direction.y - self.forward * direction.z;
+        let right = self.right() * dx;
+        let up = self.up() * dy;
+        let position = glm::to_vec3(self.position) + right * position.x + up * position.y
+            - forward * position.z;
+        let direction = right * direction.x + up * direction.y - forward * direction.z;
 
         return (position, direction);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orbiting_over_the_pole_stays_continuous() {
+        let mut camera = Camera::new();
+        camera.set_state(CameraState::looking_along(
+            glm::dvec3(0.0, 0.0, 2.0),
+            glm::dvec3(0.0, 0.0, -1.0),
+            glm::dvec3(0.0, 1.0, 0.0),
+        ));
+        camera.update_time(camera.end_time());
+        let focus = camera.position + camera.state().forward();
+
+        // Dragging down by 10 pixels turns the view by 0.05 radians, 60 of
+        // them take the camera over the top and down the far side.
+        let mut lowest = 1.0f32;
+        for _ in 0..60 {
+            let (forward, up) = (glm::normalize(camera.forward()), camera.up());
+            camera.orbit_controls(0.0, 10.0);
+            camera.update_time(camera.end_time());
+            let (next_forward, next_up) = (glm::normalize(camera.forward()), camera.up());
+            assert!(glm::dot(forward, next_forward) > 0.05f32.cos() - 1e-4);
+            assert!(glm::dot(up, next_up) > 0.05f32.cos() - 1e-4);
+            assert!(glm::length(camera.position + camera.state().forward() - focus) < 1e-9);
+            lowest = lowest.min(next_forward.y);
+        }
+        assert!(lowest < -0.999, "the camera never looked straight down");
+        assert!(
+            camera.position.z < focus.z,
+            "the camera did not reach the far side"
+        );
+    }
+}
@@ -0,0 +1,118 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+
+/// Raymarches the Mandelbulb, the Mandelbox and a few simpler shapes, either
+/// in a window or straight to an image. Without a subcommand the built-in
+/// scene opens in a window.
+#[derive(Debug, Parser)]
+#[command(version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Opens a scene in an interactive window.
+    View(ViewArgs),
+    /// Renders a single frame of a scene to a PNG without opening a window.
+    Render(RenderArgs),
+    /// Renders a flight along a camera path to numbered PNG frames without
+    /// opening a window. Frames that are already there are skipped, so an
+    /// interrupted render can be resumed by running it again.
+    Animate(AnimateArgs),
+    /// Prints the OpenGL implementation and the limits the renderer runs into.
+    Info,
+}
+
+#[derive(Debug, Default, Args)]
+pub struct ViewArgs {
+    /// Scene file to open. Without one the built-in scene is shown.
+    pub scene: Option<PathBuf>,
+    /// Size of the window's contents in pixels, like 1280x720.
+    #[arg(long, value_name = "WIDTHxHEIGHT", value_parser = parse_size)]
+    pub size: Option<(u32, u32)>,
+    /// Covers the whole monitor instead of opening a window on it.
+    #[arg(long)]
+    pub fullscreen: bool,
+    /// Shows each frame as soon as it is drawn instead of waiting for the
+    /// display to refresh.
+    #[arg(long)]
+    pub no_vsync: bool,
+    /// Reloads the fragment shader from `src/shader` whenever one of its
+    /// files changes.
+    #[arg(long)]
+    pub watch_shaders: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct RenderArgs {
+    /// Scene file to render. Without one the built-in scene is rendered.
+    pub scene: Option<PathBuf>,
+    /// Where to save the PNG.
+    #[arg(short, long, value_name = "PATH")]
+    pub output: PathBuf,
+    #[command(flatten)]
+    pub image: ImageArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct AnimateArgs {
+    /// Camera path file with the `[[keyframe]]`s to fly through, each with a
+    /// `position`, a `forward` vector and optionally an `up` vector and a
+    /// `time` in seconds.
+    pub path: PathBuf,
+    /// Scene file for everything but the camera. Without one the built-in
+    /// scene is used.
+    #[arg(long, value_name = "PATH")]
+    pub scene: Option<PathBuf>,
+    /// Directory to write frame-00000.png and the following frames to.
+    #[arg(short, long, value_name = "DIR")]
+    pub output: PathBuf,
+    /// Frames per second.
+    #[arg(long, default_value_t = 30.0, value_parser = parse_positive)]
+    pub fps: f64,
+    /// Length of the animation in seconds. Needed if the keyframes have no
+    /// times [default: the time of the last keyframe]
+    #[arg(long, value_name = "SECONDS", value_parser = parse_positive)]
+    pub duration: Option<f64>,
+    #[command(flatten)]
+    pub image: ImageArgs,
+}
+
+/// How images are rendered without a window.
+#[derive(Debug, Args)]
+pub struct ImageArgs {
+    /// Size of the image in pixels, like 1920x1080 [default: the scene's
+    /// render.width and render.height]
+    #[arg(long, value_name = "WIDTHxHEIGHT", value_parser = parse_size)]
+    pub size: Option<(u32, u32)>,
+    /// Number of jittered samples averaged for each pixel.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub samples: u32,
+    /// Largest tile drawn at once, in pixels square. Smaller tiles keep each
+    /// GPU submission short [default: the scene's render.tile_size]
+    #[arg(long, value_name = "PIXELS", value_parser = clap::value_parser!(u32).range(1..))]
+    pub tile_size: Option<u32>,
+    /// Renders on the CPU instead of with an offscreen OpenGL context.
+    #[arg(long)]
+    pub cpu: bool,
+}
+
+fn parse_size(size: &str) -> Result<(u32, u32), String> {
+    let parse = || {
+        let (width, height) = size.split_once('x')?;
+        let size = (width.parse().ok()?, height.parse().ok()?);
+        (size.0 > 0 && size.1 > 0).then_some(size)
+    };
+    parse().ok_or_else(|| format!("`{size}` should look like 1920x1080"))
+}
+
+fn parse_positive(number: &str) -> Result<f64, String> {
+    number
+        .parse()
+        .ok()
+        .filter(|number: &f64| *number > 0.0 && number.is_finite())
+        .ok_or_else(|| format!("`{number}` should be a number above 0"))
+}
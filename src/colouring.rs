@@ -0,0 +1,233 @@
+use glm::{abs, dot, length, mix, normalize, vec3};
+use serde::{Deserialize, Serialize};
+
+/// Number of texels the palette is baked into.
+pub const PALETTE_SIZE: usize = 256;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Colouring {
+    /// Surface colour, multiplied by the palette colour.
+    #[serde(with = "crate::serde_glm::vec3")]
+    pub color: glm::Vec3,
+    pub trap: OrbitTrap,
+    /// The palette is looked up at `value * scale + offset`, where `value`
+    /// comes from the orbit trap.
+    pub scale: f32,
+    pub offset: f32,
+    /// Whether lookups outside 0 to 1 wrap around instead of taking the
+    /// colour at the nearest end.
+    pub repeat: bool,
+    pub palette: Palette,
+}
+
+impl Default for Colouring {
+    fn default() -> Self {
+        Self {
+            color: vec3(1.0, 1.0, 1.0),
+            trap: OrbitTrap::default(),
+            scale: 1.0,
+            offset: 0.0,
+            repeat: false,
+            palette: Palette::default(),
+        }
+    }
+}
+
+impl Colouring {
+    /// Looks up a baked palette the way the shader's linearly filtered
+    /// texture does.
+    pub fn sample(&self, palette: &[[u8; 4]], value: f32) -> glm::Vec3 {
+        let texel = |i: i64| {
+            let i = if self.repeat {
+                i.rem_euclid(PALETTE_SIZE as i64)
+            } else {
+                i.clamp(0, PALETTE_SIZE as i64 - 1)
+            };
+            let [r, g, b, _] = palette[i as usize];
+            vec3(r as f32, g as f32, b as f32) / 255.0
+        };
+        let u = (value * self.scale + self.offset) * PALETTE_SIZE as f32 - 0.5;
+        let i = u.floor();
+        mix(
+            texel(i as i64),
+            texel(i as i64 + 1),
+            vec3(u - i, u - i, u - i),
+        )
+    }
+}
+
+/// What the palette value of a hit is computed from. The distance estimators
+/// take it to fill in their `trap` output.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OrbitTrap {
+    pub mode: ColourMode,
+    pub shape: TrapShape,
+}
+
+impl OrbitTrap {
+    /// Port of `colour_value` in `distance.glsl`.
+    pub fn value(&self, closest: f32, escape: f32, iterations: u32) -> f32 {
+        match self.mode {
+            ColourMode::Trap => closest,
+            ColourMode::Iterations => escape / iterations as f32,
+        }
+    }
+}
+
+/// The discriminants match the `COLOUR_*` defines in the shader.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColourMode {
+    /// The closest the orbit comes to the trap shape.
+    #[default]
+    Trap = 0,
+    /// The smoothed iteration at which the orbit escapes, as a fraction of
+    /// the iteration count. Points that never escape get 1.
+    Iterations = 1,
+}
+
+/// The discriminants match the `TRAP_*` defines in the shader.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TrapShape {
+    Point {
+        #[serde(with = "crate::serde_glm::vec3")]
+        center: glm::Vec3,
+    },
+    /// The points where `dot(p, normal) == offset`.
+    Plane {
+        #[serde(with = "crate::serde_glm::vec3")]
+        normal: glm::Vec3,
+        offset: f32,
+    },
+    /// A line through the origin.
+    Axis {
+        #[serde(with = "crate::serde_glm::vec3")]
+        direction: glm::Vec3,
+    },
+    Sphere {
+        #[serde(with = "crate::serde_glm::vec3")]
+        center: glm::Vec3,
+        radius: f32,
+    },
+}
+
+impl Default for TrapShape {
+    fn default() -> Self {
+        TrapShape::Point {
+            center: vec3(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+impl TrapShape {
+    /// The shape as the `trap_shape`, `trap_vector` and `trap_scalar`
+    /// uniforms, with directions normalised.
+    pub fn uniforms(&self) -> (i32, glm::Vec3, f32) {
+        match *self {
+            TrapShape::Point { center } => (0, center, 0.0),
+            TrapShape::Plane { normal, offset } => (1, normalize(normal), offset),
+            TrapShape::Axis { direction } => (2, normalize(direction), 0.0),
+            TrapShape::Sphere { center, radius } => (3, center, radius),
+        }
+    }
+
+    /// Port of `trap_distance` in `distance.glsl`.
+    pub fn distance(&self, z: glm::Vec3) -> f32 {
+        let (shape, vector, scalar) = self.uniforms();
+        match shape {
+            1 => abs(dot(z, vector) - scalar),
+            2 => length(z - vector * dot(z, vector)),
+            3 => abs(length(z - vector) - scalar),
+            _ => length(z - vector),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Palette {
+    /// `a + b * cos(2 pi (c * t + d))`, see
+    /// https://iquilezles.org/articles/palettes/
+    Cosine {
+        #[serde(with = "crate::serde_glm::vec3")]
+        a: glm::Vec3,
+        #[serde(with = "crate::serde_glm::vec3")]
+        b: glm::Vec3,
+        #[serde(with = "crate::serde_glm::vec3")]
+        c: glm::Vec3,
+        #[serde(with = "crate::serde_glm::vec3")]
+        d: glm::Vec3,
+    },
+    /// Linear interpolation between colour stops, which need not be sorted.
+    Gradient { stops: Vec<GradientStop> },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GradientStop {
+    pub position: f32,
+    #[serde(with = "crate::serde_glm::vec3")]
+    pub color: glm::Vec3,
+}
+
+impl Default for Palette {
+    /// Black to white, so the trap value shows as brightness.
+    fn default() -> Self {
+        Palette::Gradient {
+            stops: vec![
+                GradientStop {
+                    position: 0.0,
+                    color: vec3(0.0, 0.0, 0.0),
+                },
+                GradientStop {
+                    position: 1.0,
+                    color: vec3(1.0, 1.0, 1.0),
+                },
+            ],
+        }
+    }
+}
+
+impl Palette {
+    /// The colour at `t` between 0 and 1.
+    pub fn color(&self, t: f32) -> glm::Vec3 {
+        match self {
+            Palette::Cosine { a, b, c, d } => {
+                let angle = (*c * t + *d) * std::f32::consts::TAU;
+                *a + *b * vec3(angle.x.cos(), angle.y.cos(), angle.z.cos())
+            }
+            Palette::Gradient { stops } => {
+                let mut stops: Vec<_> = stops.iter().collect();
+                stops.sort_by(|a, b| a.position.total_cmp(&b.position));
+                let Some(first) = stops.first() else {
+                    return vec3(1.0, 1.0, 1.0);
+                };
+                let mut color = first.color;
+                for pair in stops.windows(2) {
+                    let (from, to) = (pair[0], pair[1]);
+                    if t >= to.position {
+                        color = to.color;
+                    } else if t > from.position {
+                        let f = (t - from.position) / (to.position - from.position);
+                        color = mix(from.color, to.color, vec3(f, f, f));
+                    }
+                }
+                color
+            }
+        }
+    }
+
+    /// The palette as RGBA8 texels, as uploaded to the shader.
+    pub fn bake(&self) -> Vec<[u8; 4]> {
+        (0..PALETTE_SIZE)
+            .map(|i| {
+                let color = self.color((i as f32 + 0.5) / PALETTE_SIZE as f32);
+                let [r, g, b] =
+                    [color.x, color.y, color.z].map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+                [r, g, b, 255]
+            })
+            .collect()
+    }
+}
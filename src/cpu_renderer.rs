@@ -0,0 +1,209 @@
+use glm::vec3;
+use rayon::prelude::*;
+
+use crate::{
+    camera::{ray_direction, sample_offset, Camera},
+    image::Image,
+    reference_orbit::ReferenceOrbit,
+    scene::Scene,
+    shader::{
+        perturbation::DistanceField,
+        shading::{self, AtmosphereWeights},
+    },
+    tiles::Tile,
+};
+
+/// Port of `ESCAPE_DISTANCE` in `mandelbulb.glsl`.
+const ESCAPE_DISTANCE: f32 = 100.0;
+
+struct Hit {
+    /// Relative to the camera.
+    position: glm::Vec3,
+    color: glm::Vec3,
+    normal: glm::Vec3,
+}
+
+/// Where a ray ended up, and after how many march steps over what distance.
+struct March {
+    hit: Option<Hit>,
+    steps: u32,
+    distance: f32,
+}
+
+/// Renders the scene on the CPU using the same rays, march loop and shading as
+/// the fragment shader. The image is drawn one tile of at most `tile_size`
+/// pixels square at a time, calling `progress` with the number of finished
+/// and total tiles after each, and the rows of a tile are spread across all
+/// cores. Each pixel is the average of `samples` jittered samples, at the
+/// same offsets as on the GPU.
+pub fn render(
+    scene: &Scene,
+    width: u32,
+    height: u32,
+    tile_size: u32,
+    samples: u32,
+    mut progress: impl FnMut(usize, usize),
+) -> Image {
+    let mut camera = scene.camera.clone();
+    camera.set_aspect(width as f32, height as f32);
+    let corners = camera.get_corners();
+    let reference = ReferenceOrbit::new(scene);
+
+    let tiles = Tile::split(width, height, tile_size);
+    let mut image = Image::new(width, height);
+    for (i, tile) in tiles.iter().enumerate() {
+        let tile_image = render_tile(
+            scene,
+            &camera,
+            reference.as_ref(),
+            &tile.corners(&corners, width, height),
+            tile.width,
+            tile.height,
+            samples,
+        );
+        image.paste(&tile_image, tile.x, tile.y);
+        progress(i + 1, tiles.len());
+    }
+    image
+}
+
+/// Renders a `width` x `height` image whose corner pixels look along
+/// `corners`, from the position of `camera`.
+fn render_tile(
+    scene: &Scene,
+    camera: &Camera,
+    reference: Option<&ReferenceOrbit>,
+    corners: &[glm::Vec3; 4],
+    width: u32,
+    height: u32,
+    samples: u32,
+) -> Image {
+    let stop_distance = camera.get_stop_distance();
+    let field = DistanceField::new(scene, reference);
+    let palette = scene.colouring.palette.bake();
+    let atmosphere = AtmosphereWeights::new(&scene.atmosphere, camera.focal_distance as f32);
+
+    let mut image = Image::new(width, height);
+    image
+        .pixels
+        .par_chunks_mut(4 * width as usize)
+        .enumerate()
+        .for_each(|(row, pixels)| {
+            for (col, pixel) in pixels.chunks_exact_mut(4).enumerate() {
+                let mut sum = glm::vec4(0.0, 0.0, 0.0, 0.0);
+                for sample in 0..samples.max(1) {
+                    let offset = sample_offset(sample);
+                    let u = (col as f32 + 0.5 + offset.x) / width as f32;
+                    let v = 1.0 - (row as f32 + 0.5 - offset.y) / height as f32;
+                    let ray = ray_direction(corners, u, v);
+                    let march = cast_ray(scene, &field, &palette, ray, stop_distance);
+                    let color = shade(scene, &field, &atmosphere, march, ray, stop_distance);
+                    sum = sum + color;
+                }
+                pixel.copy_from_slice(&to_rgba8(sum / samples.max(1) as f32));
+            }
+        });
+    image
+}
+
+fn normal(field: &DistanceField, p: glm::Vec3, epsilon: f32) -> glm::Vec3 {
+    let center_distance = field.distance(&p);
+    let x_distance = field.distance(&(p + vec3(epsilon, 0.0, 0.0)));
+    let y_distance = field.distance(&(p + vec3(0.0, epsilon, 0.0)));
+    let z_distance = field.distance(&(p + vec3(0.0, 0.0, epsilon)));
+    (vec3(x_distance, y_distance, z_distance) - center_distance) / epsilon
+}
+
+/// Marches relative to the camera like `cast_ray` in `mandelbulb.glsl`.
+fn cast_ray(
+    scene: &Scene,
+    field: &DistanceField,
+    palette: &[[u8; 4]],
+    ray: glm::Vec3,
+    stop_distance: f32,
+) -> March {
+    let mut offset = glm::vec3(0.0, 0.0, 0.0);
+    let mut steps = scene.render.max_steps;
+    let mut distance = 0.0;
+
+    for step in 0..scene.render.max_steps {
+        let (d, value) = field.distance_and_colour(&offset, &scene.colouring.trap);
+
+        offset = offset + ray * d;
+        distance += d;
+
+        if d < stop_distance {
+            return March {
+                hit: Some(Hit {
+                    position: offset,
+                    color: scene.colouring.color * scene.colouring.sample(palette, value),
+                    normal: normal(field, offset, stop_distance),
+                }),
+                steps: step + 1,
+                distance,
+            };
+        }
+        if d > ESCAPE_DISTANCE && glm::dot(field.world_position(&offset), ray) > 0.0 {
+            steps = step + 1;
+            break;
+        }
+    }
+
+    March {
+        hit: None,
+        steps,
+        distance,
+    }
+}
+
+fn shade(
+    scene: &Scene,
+    field: &DistanceField,
+    atmosphere: &AtmosphereWeights,
+    march: March,
+    ray: glm::Vec3,
+    stop_distance: f32,
+) -> glm::Vec4 {
+    let dir = glm::normalize(ray);
+    let glow = shading::glow_light(
+        march.steps,
+        scene.render.max_steps,
+        march.distance,
+        atmosphere,
+    );
+    match march.hit {
+        None => {
+            let color = scene.background.color(dir, &scene.light) + glow;
+            glm::vec4(color.x, color.y, color.z, 1.0)
+        }
+        Some(hit) => {
+            let n = glm::normalize(hit.normal);
+            let shadow = shading::soft_shadow(
+                field,
+                hit.position,
+                n,
+                stop_distance,
+                &scene.light,
+                &scene.shading,
+            );
+            let focal_distance = scene.camera.focal_distance as f32;
+            let occlusion =
+                shading::ambient_occlusion(field, hit.position, n, &scene.shading, focal_distance)
+                    * shading::step_occlusion(march.steps, scene.render.max_steps, &scene.shading);
+            let color = shading::shade(hit.color, n, dir, shadow, occlusion, scene) + glow;
+            let t = glm::length(hit.position);
+            let (high, low) = scene.camera.split_position();
+            let fog = shading::fog_amount(high.y + low.y, dir, t, atmosphere);
+            let color = glm::mix(
+                color,
+                scene.background.fog_colour(dir),
+                glm::vec3(fog, fog, fog),
+            );
+            glm::vec4(color.x, color.y, color.z, 1.0)
+        }
+    }
+}
+
+fn to_rgba8(color: glm::Vec4) -> [u8; 4] {
+    [color.x, color.y, color.z, color.w].map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8)
+}
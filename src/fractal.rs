@@ -0,0 +1,512 @@
+use glm::{length, max, min, vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::colouring::OrbitTrap;
+use crate::shader::mandelbox::mandelbox;
+use crate::shader::mandelbulb::mandel;
+use crate::shader::primitives::{sd_round_box, sphere};
+
+/// The distance estimator drawn by `my_mandel` in `distance.glsl`. The
+/// discriminants match the `FORMULA_*` defines in the shader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Formula {
+    Mandelbulb = 0,
+    Mandelbox = 1,
+    RoundBox = 2,
+    Sphere = 3,
+}
+
+impl Formula {
+    pub const ALL: [Formula; 4] = [
+        Formula::Mandelbulb,
+        Formula::Mandelbox,
+        Formula::RoundBox,
+        Formula::Sphere,
+    ];
+
+    /// The formula after this one, wrapping around.
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Fractal {
+    pub formula: Formula,
+    /// Mandelbulb power.
+    pub power: f32,
+    /// Mandelbulb phase, added to the polar angle before raising to `power`.
+    pub phase: f32,
+    pub bulb_iterations: u32,
+    /// Mandelbox scale. Negative values give the other common family of boxes.
+    pub scale: f32,
+    /// Squared radius below which the Mandelbox sphere fold scales linearly.
+    pub min_rad2: f32,
+    pub box_iterations: u32,
+}
+
+impl Default for Fractal {
+    fn default() -> Self {
+        Self {
+            formula: Formula::Mandelbulb,
+            power: 4.0,
+            phase: 0.0,
+            bulb_iterations: 32,
+            scale: 3.0,
+            min_rad2: 0.5,
+            box_iterations: 16,
+        }
+    }
+}
+
+impl Fractal {
+    /// Port of `my_mandel` in `distance.glsl`. Returns the distance estimate
+    /// and the palette value from `trap`.
+    pub fn distance_and_colour(&self, p: &glm::Vec3, trap: &OrbitTrap) -> (f32, f32) {
+        match self.formula {
+            Formula::Mandelbulb => {
+                let (d, value) = mandel(p, self.power, self.phase, self.bulb_iterations, trap);
+                (min(d, length(*p)), value)
+            }
+            Formula::Mandelbox => {
+                mandelbox(p, self.scale, self.min_rad2, self.box_iterations, trap)
+            }
+            Formula::RoundBox => (sd_round_box(p, &vec3(1.0, 0.8, 0.6), 0.1), 1.0),
+            Formula::Sphere => (sphere(p, &vec3(0.0, 0.0, 0.0), 1.0), 1.0),
+        }
+    }
+
+    pub fn distance(&self, p: &glm::Vec3) -> f32 {
+        self.distance_and_colour(p, &OrbitTrap::default()).0
+    }
+
+    /// Distance used to pick the pivot when zooming. It is kept away from zero
+    /// so that zooming out from inside or on the surface still works.
+    pub fn zoom_distance(&self, p: &glm::Vec3) -> f32 {
+        max(0.001, self.distance(p))
+    }
+}
+
+/// Compares `Fractal::distance` with `my_mandel` from `distance.glsl` evaluated
+/// on the GPU, so that the two cannot drift apart. The GPU tests need a
+/// headless GL context and fail without one.
+#[cfg(all(test, not(apple)))]
+mod tests {
+    use std::ffi::CString;
+    use std::ptr::null;
+
+    use glutin::prelude::GlDisplay;
+
+    use super::*;
+    use crate::colouring::{ColourMode, TrapShape};
+    use crate::headless::HeadlessContext;
+    use crate::reference_orbit::ReferenceOrbit;
+    use crate::renderer::{create_shader, gl, ShaderStage, UniformData};
+    use crate::scene::Scene;
+    use crate::shader::perturbation::DistanceField;
+    use crate::shader::source::DISTANCE_SHADER_SOURCE;
+    use crate::shader::version::GlslVersion;
+    use crate::track::CameraState;
+
+    /// Points are evaluated as the pixels of a `SIZE` x `SIZE` image.
+    const SIZE: i32 = 64;
+
+    const VERTEX_SHADER_SOURCE: &[u8] = b"
+#version 330
+in vec2 position;
+
+void main() {
+    gl_Position = vec4(position, 0.0, 1.0);
+}
+\0";
+
+    /// In deep zoom mode the points are offsets from the camera.
+    const FRAGMENT_SHADER_MAIN: &str = "
+uniform sampler2D points;
+
+void main() {
+    float trap;
+    vec3 p = texelFetch(points, ivec2(gl_FragCoord.xy), 0).xyz;
+    float d = deep_zoom != 0 ? scene_distance(p, trap) : my_mandel(p, trap);
+    FRAG_COLOR = vec4(d, trap, 0.0, 1.0);
+}
+";
+
+    /// Deterministic points spread over a cube of half size `extent`.
+    fn sample_points(extent: f32) -> Vec<[f32; 4]> {
+        let mut state: u32 = 0x9e3779b9;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            (state as f32 / u32::MAX as f32 * 2.0 - 1.0) * extent
+        };
+        (0..SIZE * SIZE)
+            .map(|_| [next(), next(), next(), 0.0])
+            .collect()
+    }
+
+    unsafe fn texture(gl: &gl::Gl, data: *const f32) -> gl::types::GLuint {
+        let mut texture = 0;
+        gl.GenTextures(1, &mut texture);
+        gl.BindTexture(gl::TEXTURE_2D, texture);
+        gl.TexImage2D(
+            gl::TEXTURE_2D,
+            0,
+            gl::RGBA32F as i32,
+            SIZE,
+            SIZE,
+            0,
+            gl::RGBA,
+            gl::FLOAT,
+            data as *const _,
+        );
+        gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+        gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+        texture
+    }
+
+    /// Returns the distance and trap computed by the shader for each point.
+    fn gpu_distances(
+        context: &HeadlessContext,
+        scene: &Scene,
+        reference: Option<&ReferenceOrbit>,
+        points: &[[f32; 4]],
+    ) -> Vec<[f32; 4]> {
+        let gl = gl::Gl::load_with(|symbol| {
+            let symbol = CString::new(symbol).unwrap();
+            context.display().get_proc_address(symbol.as_c_str()).cast()
+        });
+        let preamble = GlslVersion::Core330.preamble(ShaderStage::Fragment);
+        let fragment_source = [
+            &preamble,
+            DISTANCE_SHADER_SOURCE,
+            FRAGMENT_SHADER_MAIN,
+            "\0",
+        ]
+        .concat();
+        let mut result = vec![[0.0; 4]; points.len()];
+
+        unsafe {
+            let program = gl.CreateProgram();
+            let vertex_shader =
+                create_shader(&gl, gl::VERTEX_SHADER, VERTEX_SHADER_SOURCE).unwrap();
+            let fragment_shader =
+                create_shader(&gl, gl::FRAGMENT_SHADER, fragment_source.as_bytes()).unwrap();
+            gl.AttachShader(program, vertex_shader);
+            gl.AttachShader(program, fragment_shader);
+            gl.BindAttribLocation(program, 0, c"position".as_ptr());
+            gl.LinkProgram(program);
+            let mut linked = 0;
+            gl.GetProgramiv(program, gl::LINK_STATUS, &mut linked);
+            assert_ne!(linked, 0, "test shader failed to link");
+            gl.UseProgram(program);
+
+            let uniform_data = [UniformData::new(scene, reference)];
+            let mut uniform_bo = 0;
+            gl.GenBuffers(1, &mut uniform_bo);
+            gl.BindBuffer(gl::UNIFORM_BUFFER, uniform_bo);
+            gl.BufferData(
+                gl::UNIFORM_BUFFER,
+                std::mem::size_of::<UniformData>() as isize,
+                uniform_data.as_ptr() as *const _,
+                gl::STATIC_DRAW,
+            );
+            let block = gl.GetUniformBlockIndex(program, c"uni".as_ptr());
+            gl.UniformBlockBinding(program, block, 0);
+            gl.BindBufferBase(gl::UNIFORM_BUFFER, 0, uniform_bo);
+
+            let points_texture = texture(&gl, points.as_ptr() as *const f32);
+            let target_texture = texture(&gl, null());
+            let mut fbo = 0;
+            gl.GenFramebuffers(1, &mut fbo);
+            gl.BindFramebuffer(gl::FRAMEBUFFER, fbo);
+            gl.FramebufferTexture2D(
+                gl::FRAMEBUFFER,
+                gl::COLOR_ATTACHMENT0,
+                gl::TEXTURE_2D,
+                target_texture,
+                0,
+            );
+            if let Some(reference) = reference {
+                let mut reference_texture = 0;
+                gl.ActiveTexture(gl::TEXTURE1);
+                gl.GenTextures(1, &mut reference_texture);
+                gl.BindTexture(gl::TEXTURE_2D, reference_texture);
+                gl.TexImage2D(
+                    gl::TEXTURE_2D,
+                    0,
+                    gl::RGB32F as i32,
+                    reference.points.len() as i32,
+                    1,
+                    0,
+                    gl::RGB,
+                    gl::FLOAT,
+                    reference.points.as_ptr() as *const _,
+                );
+                gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+                gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+                let location = gl.GetUniformLocation(program, c"reference_orbit".as_ptr());
+                gl.Uniform1i(location, 1);
+                gl.ActiveTexture(gl::TEXTURE0);
+            }
+            gl.BindTexture(gl::TEXTURE_2D, points_texture);
+
+            let quad: [f32; 8] = [-1.0, -1.0, -1.0, 1.0, 1.0, -1.0, 1.0, 1.0];
+            let (mut vao, mut vbo) = (0, 0);
+            gl.GenVertexArrays(1, &mut vao);
+            gl.BindVertexArray(vao);
+            gl.GenBuffers(1, &mut vbo);
+            gl.BindBuffer(gl::ARRAY_BUFFER, vbo);
+            gl.BufferData(
+                gl::ARRAY_BUFFER,
+                std::mem::size_of_val(&quad) as isize,
+                quad.as_ptr() as *const _,
+                gl::STATIC_DRAW,
+            );
+            gl.VertexAttribPointer(0, 2, gl::FLOAT, 0, 0, null());
+            gl.EnableVertexAttribArray(0);
+
+            gl.Viewport(0, 0, SIZE, SIZE);
+            gl.DrawArrays(gl::TRIANGLE_STRIP, 0, 4);
+            gl.ReadPixels(
+                0,
+                0,
+                SIZE,
+                SIZE,
+                gl::RGBA,
+                gl::FLOAT,
+                result.as_mut_ptr() as *mut _,
+            );
+            assert_eq!(gl.GetError(), gl::NO_ERROR);
+        }
+
+        result
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a.is_nan() && b.is_nan()) || (a - b).abs() <= 1e-4 + 1e-3 * b.abs()
+    }
+
+    /// Checks that at most `allowed` of the points sampled in a cube of half
+    /// size `extent` get a different distance or palette value on the CPU. In
+    /// deep zoom mode the cube is centred on the camera's focus and the points
+    /// go through the perturbed estimators on both sides.
+    fn check_scene(scene: &Scene, extent: f32, allowed: f32) {
+        let context = HeadlessContext::new()
+            .unwrap_or_else(|err| panic!("the shader tests need a headless GL context: {err}"));
+        let reference = ReferenceOrbit::new(scene);
+        let field = DistanceField::new(scene, reference.as_ref());
+        let mut points = sample_points(extent);
+        if reference.is_some() {
+            let focus = scene.camera.forward();
+            for p in &mut points {
+                p[0] += focus.x;
+                p[1] += focus.y;
+                p[2] += focus.z;
+            }
+        }
+        let gpu = gpu_distances(&context, scene, reference.as_ref(), &points);
+
+        let mismatches: Vec<_> = points
+            .iter()
+            .zip(&gpu)
+            .filter_map(|(p, gpu)| {
+                let p3 = glm::vec3(p[0], p[1], p[2]);
+                let (d, value) = match reference {
+                    Some(_) => field.distance_and_colour(&p3, &scene.colouring.trap),
+                    None => scene
+                        .fractal
+                        .distance_and_colour(&p3, &scene.colouring.trap),
+                };
+                (!close(d, gpu[0]) || !close(value, gpu[1])).then_some((p, (d, value), gpu))
+            })
+            .collect();
+
+        assert!(
+            mismatches.len() as f32 <= allowed * points.len() as f32,
+            "{} of {} points differ for {:?} coloured by {:?}, e.g. {:?}",
+            mismatches.len(),
+            points.len(),
+            scene.fractal.formula,
+            scene.colouring.trap,
+            &mismatches[..mismatches.len().min(5)]
+        );
+    }
+
+    fn check_formula(formula: Formula, extent: f32, allowed: f32) {
+        let mut scene = Scene::init();
+        scene.fractal.formula = formula;
+        check_scene(&scene, extent, allowed);
+    }
+
+    /// Distances and palette values of the default fractals, as the shader
+    /// computed them when the estimators last changed on purpose.
+    const STORED_VALUES: [(Formula, [f32; 3], f32, f32); 12] = [
+        (Formula::Mandelbulb, [-0.7, 0.1, 0.4], 0.0291657, 0.8124038),
+        (Formula::Mandelbulb, [1.2, -0.9, 0.6], 0.21308103, 1.6155494),
+        (
+            Formula::Mandelbulb,
+            [0.05, 0.6, 0.7],
+            0.020465882,
+            0.92330927,
+        ),
+        (Formula::Mandelbox, [0.5, 0.3, -0.2], 0.050210644, 4.3150897),
+        (Formula::Mandelbox, [1.2, -0.9, 0.6], 0.10846524, 5.3413477),
+        (
+            Formula::Mandelbox,
+            [0.05, 0.6, 0.7],
+            1.7248728e-7,
+            1.1874442,
+        ),
+        (Formula::RoundBox, [0.5, 0.3, -0.2], -0.40000004, 1.0),
+        (Formula::RoundBox, [1.2, -0.9, 0.6], 0.27416578, 1.0),
+        (Formula::RoundBox, [0.05, 0.6, 0.7], 0.09999996, 1.0),
+        (Formula::Sphere, [0.5, 0.3, -0.2], -0.38355863, 1.0),
+        (Formula::Sphere, [1.2, -0.9, 0.6], 0.61554945, 1.0),
+        (Formula::Sphere, [0.05, 0.6, 0.7], -0.07669073, 1.0),
+    ];
+
+    /// Catches changes to the estimators without a GL context. The shader
+    /// tests below check that these values still match `distance.glsl`.
+    #[test]
+    fn distances_match_stored_values() {
+        for (formula, p, distance, value) in STORED_VALUES {
+            let mut scene = Scene::init();
+            scene.fractal.formula = formula;
+            let (d, v) = scene
+                .fractal
+                .distance_and_colour(&glm::vec3(p[0], p[1], p[2]), &scene.colouring.trap);
+            assert!(
+                close(d, distance) && close(v, value),
+                "{formula:?} at {p:?} gives {:?}, stored {:?}",
+                (d, v),
+                (distance, value)
+            );
+        }
+    }
+
+    #[test]
+    fn mandelbulb_matches_shader() {
+        // Orbits that stay inside the bulb are chaotic, so differences in the
+        // last bits of `pow` and `atan` between drivers add up over the
+        // iterations. A real change to the formula breaks almost every point.
+        check_formula(Formula::Mandelbulb, 1.5, 0.1);
+    }
+
+    #[test]
+    fn mandelbox_matches_shader() {
+        check_formula(Formula::Mandelbox, 5.0, 0.01);
+    }
+
+    #[test]
+    fn round_box_matches_shader() {
+        check_formula(Formula::RoundBox, 2.0, 0.01);
+    }
+
+    #[test]
+    fn sphere_matches_shader() {
+        check_formula(Formula::Sphere, 2.0, 0.01);
+    }
+
+    #[test]
+    fn trap_shapes_match_shader() {
+        let shapes = [
+            TrapShape::Point {
+                center: glm::vec3(0.2, -0.1, 0.3),
+            },
+            TrapShape::Plane {
+                normal: glm::vec3(1.0, 2.0, 0.5),
+                offset: 0.3,
+            },
+            TrapShape::Axis {
+                direction: glm::vec3(0.0, 1.0, 1.0),
+            },
+            TrapShape::Sphere {
+                center: glm::vec3(0.0, 0.0, 0.5),
+                radius: 0.7,
+            },
+        ];
+        for formula in [Formula::Mandelbulb, Formula::Mandelbox] {
+            for shape in shapes {
+                let mut scene = Scene::init();
+                scene.fractal.formula = formula;
+                scene.colouring.trap.shape = shape;
+                check_scene(&scene, 1.5, 0.1);
+            }
+        }
+    }
+
+    #[test]
+    fn iteration_colouring_matches_shader() {
+        for (formula, extent) in [(Formula::Mandelbulb, 1.5), (Formula::Mandelbox, 5.0)] {
+            let mut scene = Scene::init();
+            scene.fractal.formula = formula;
+            scene.colouring.trap.mode = ColourMode::Iterations;
+            check_scene(&scene, extent, 0.1);
+        }
+    }
+
+    /// A shallow view of `formula` in deep zoom mode, with the focus off the
+    /// z axis so the reference orbit does not stop at once.
+    fn deep_zoom_scene(formula: Formula) -> Scene {
+        let mut scene = Scene::init();
+        scene.fractal.formula = formula;
+        scene.render.deep_zoom = true;
+        scene.camera.set_state(CameraState::looking_along(
+            glm::dvec3(0.2, 0.3, 2.0),
+            glm::dvec3(0.0, 0.0, -1.0),
+            glm::dvec3(0.0, 1.0, 0.0),
+        ));
+        scene.settle();
+        scene
+    }
+
+    #[test]
+    fn perturbed_mandel_matches_shader() {
+        check_scene(&deep_zoom_scene(Formula::Mandelbulb), 1.5, 0.1);
+    }
+
+    #[test]
+    fn perturbed_mandelbox_matches_shader() {
+        check_scene(&deep_zoom_scene(Formula::Mandelbox), 2.0, 0.01);
+    }
+
+    #[test]
+    fn deep_zoom_matches_flat_field_at_shallow_zoom() {
+        // The reference orbit only pays off far below the spacing of floats
+        // around the camera. Close to it, following the orbit must give the
+        // same field as iterating each point.
+        for (formula, extent) in [(Formula::Mandelbulb, 0.5), (Formula::Mandelbox, 2.0)] {
+            let scene = deep_zoom_scene(formula);
+            let reference = ReferenceOrbit::new(&scene);
+            let deep = DistanceField::new(&scene, reference.as_ref());
+            let focus = scene.camera.forward();
+            let trap = &scene.colouring.trap;
+
+            let points = sample_points(extent);
+            let mismatches: Vec<_> = points
+                .iter()
+                .filter_map(|p| {
+                    let offset = glm::vec3(p[0], p[1], p[2]) + focus;
+                    let flat = scene
+                        .fractal
+                        .distance_and_colour(&deep.world_position(&offset), trap);
+                    let perturbed = deep.distance_and_colour(&offset, trap);
+                    (!close(perturbed.0, flat.0) || !close(perturbed.1, flat.1))
+                        .then_some((offset, perturbed, flat))
+                })
+                .collect();
+            assert!(
+                mismatches.len() * 20 <= points.len(),
+                "{} of {} points differ for {:?}, e.g. {:?}",
+                mismatches.len(),
+                points.len(),
+                formula,
+                &mismatches[..mismatches.len().min(5)]
+            );
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use std::{
+    error::Error,
+    fs,
+    io::{BufRead, Read},
+    path::Path,
+};
+
+/// A floating point RGB image with rows stored from top to bottom, read from
+/// a Radiance `.hdr` file.
+pub struct HdrImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<glm::Vec3>,
+}
+
+impl HdrImage {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        Self::decode(&fs::read(path)?)
+    }
+
+    /// Decodes the RGBE format, both with and without run length encoded
+    /// scanlines. Only the usual `-Y height +X width` orientation is
+    /// supported.
+    pub fn decode(mut data: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let mut line = String::new();
+        data.read_line(&mut line)?;
+        if !line.starts_with("#?") {
+            return Err("not a Radiance HDR file".into());
+        }
+        loop {
+            line.clear();
+            if data.read_line(&mut line)? == 0 {
+                return Err("HDR header has no resolution line".into());
+            }
+            let line = line.trim();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(format) = line.strip_prefix("FORMAT=") {
+                if format != "32-bit_rle_rgbe" {
+                    return Err(format!("unsupported HDR pixel format {format}").into());
+                }
+            }
+        }
+
+        line.clear();
+        data.read_line(&mut line)?;
+        let (height, width) = match line.split_whitespace().collect::<Vec<_>>()[..] {
+            ["-Y", height, "+X", width] => (height.parse::<u32>()?, width.parse::<u32>()?),
+            _ => return Err(format!("unsupported HDR orientation {}", line.trim()).into()),
+        };
+
+        // Checked before anything is allocated for the pixels, so a broken or
+        // hostile header can not ask for more memory than the file could
+        // fill.
+        if width == 0 || height == 0 {
+            return Err("HDR image is empty".into());
+        }
+        if (data.len() as u64) < min_scanline_len(width) * height as u64 {
+            return Err(format!("HDR data is too short for a {width}x{height} image").into());
+        }
+
+        let mut pixels = Vec::with_capacity(width as usize * height as usize);
+        let mut scanline = vec![[0u8; 4]; width as usize];
+        for _ in 0..height {
+            read_scanline(&mut data, &mut scanline)?;
+            pixels.extend(scanline.iter().map(|&rgbe| rgbe_to_float(rgbe)));
+        }
+
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+}
+
+/// The fewest bytes a scanline of `width` pixels can take, with every channel
+/// in runs of the longest length.
+fn min_scanline_len(width: u32) -> u64 {
+    if (8..0x8000).contains(&width) {
+        4 + 4 * 2 * width.div_ceil(127) as u64
+    } else {
+        4 * width as u64
+    }
+}
+
+fn read_scanline(data: &mut &[u8], scanline: &mut [[u8; 4]]) -> Result<(), Box<dyn Error>> {
+    let mut header = [0u8; 4];
+    data.read_exact(&mut header)?;
+    let width = scanline.len();
+
+    // New style run length encoding starts with 2, 2 and the width, and
+    // stores each of the four channels separately.
+    let run_length_encoded =
+        (8..0x8000).contains(&width) && header[0] == 2 && header[1] == 2 && header[2] & 0x80 == 0;
+    if !run_length_encoded {
+        scanline[0] = header;
+        for pixel in &mut scanline[1..] {
+            data.read_exact(pixel)?;
+        }
+        return Ok(());
+    }
+    if ((header[2] as usize) << 8 | header[3] as usize) != width {
+        return Err("HDR scanline length does not match the image width".into());
+    }
+
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let mut count = [0u8];
+            data.read_exact(&mut count)?;
+            let count = count[0] as usize;
+            if count > 128 {
+                let count = count - 128;
+                if x + count > width {
+                    return Err("HDR run goes past the end of the scanline".into());
+                }
+                let mut value = [0u8];
+                data.read_exact(&mut value)?;
+                for pixel in &mut scanline[x..x + count] {
+                    pixel[channel] = value[0];
+                }
+                x += count;
+            } else {
+                if count == 0 || x + count > width {
+                    return Err("HDR run goes past the end of the scanline".into());
+                }
+                let mut values = [0u8; 128];
+                data.read_exact(&mut values[..count])?;
+                for (pixel, value) in scanline[x..x + count].iter_mut().zip(values) {
+                    pixel[channel] = value;
+                }
+                x += count;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn rgbe_to_float([r, g, b, e]: [u8; 4]) -> glm::Vec3 {
+    if e == 0 {
+        return glm::vec3(0.0, 0.0, 0.0);
+    }
+    let scale = 2f32.powi(e as i32 - (128 + 8));
+    glm::vec3(r as f32, g as f32, b as f32) * scale
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(resolution: &str, scanlines: &[u8]) -> Vec<u8> {
+        let header = format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n{resolution}\n");
+        [header.as_bytes(), scanlines].concat()
+    }
+
+    #[test]
+    fn decodes_flat_scanlines() {
+        let data = file(
+            "-Y 2 +X 3",
+            &[
+                128, 64, 32, 129, 0, 0, 0, 0, 255, 255, 255, 120, //
+                1, 2, 3, 136, 128, 128, 128, 128, 64, 0, 0, 128,
+            ],
+        );
+        let image = HdrImage::decode(&data).unwrap();
+        assert_eq!((image.width, image.height), (3, 2));
+        assert_eq!(
+            image.pixels,
+            [
+                glm::vec3(1.0, 0.5, 0.25),
+                glm::vec3(0.0, 0.0, 0.0),
+                glm::vec3(255.0, 255.0, 255.0) / 65536.0,
+                glm::vec3(1.0, 2.0, 3.0),
+                glm::vec3(0.5, 0.5, 0.5),
+                glm::vec3(0.25, 0.0, 0.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn decodes_run_length_encoded_scanlines() {
+        let data = file(
+            "-Y 1 +X 8",
+            &[
+                2, 2, 0, 8, // New style scanline of 8 pixels.
+                136, 128, // Red: a run of 8.
+                8, 0, 16, 32, 48, 64, 80, 96, 112, // Green: 8 literal values.
+                132, 0, 132, 64, // Blue: two runs of 4.
+                136, 129, // Exponent: a run of 8.
+            ],
+        );
+        let image = HdrImage::decode(&data).unwrap();
+        assert_eq!((image.width, image.height), (8, 1));
+        let expected: Vec<_> = (0..8)
+            .map(|x| {
+                let blue = if x < 4 { 0.0 } else { 64.0 };
+                glm::vec3(128.0, 16.0 * x as f32, blue) / 128.0
+            })
+            .collect();
+        assert_eq!(image.pixels, expected);
+    }
+
+    #[test]
+    fn rejects_sizes_the_data_can_not_hold() {
+        let data = file("-Y 100000 +X 100000", &[2, 2, 0x86, 0xa0, 136, 128]);
+        assert!(HdrImage::decode(&data).is_err());
+        assert!(HdrImage::decode(&file("-Y 0 +X 8", &[])).is_err());
+    }
+
+    #[test]
+    fn rejects_runs_past_the_scanline() {
+        let data = file("-Y 1 +X 8", &[2, 2, 0, 8, 137, 128, 0, 0, 0, 0, 0, 0]);
+        assert!(HdrImage::decode(&data).is_err());
+    }
+}
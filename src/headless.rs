@@ -0,0 +1,78 @@
+use std::error::Error;
+use std::num::NonZeroU32;
+
+use glutin::api::egl::context::PossiblyCurrentContext;
+use glutin::api::egl::device::Device;
+use glutin::api::egl::display::Display;
+use glutin::api::egl::surface::Surface;
+use glutin::config::{ConfigSurfaceTypes, ConfigTemplateBuilder};
+use glutin::context::{ContextApi, ContextAttributesBuilder};
+use glutin::prelude::*;
+use glutin::surface::{PbufferSurface, SurfaceAttributesBuilder};
+
+/// A GL context that is not tied to any window, for rendering on machines
+/// without a display server. It works with software implementations such as
+/// llvmpipe through the EGL device platform.
+pub struct HeadlessContext {
+    // NOTE: Drop order matters, the display has to outlive the context.
+    _context: PossiblyCurrentContext,
+    _surface: Option<Surface<PbufferSurface>>,
+    display: Display,
+}
+
+impl HeadlessContext {
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        let device = Device::query_devices()?
+            .next()
+            .ok_or("no EGL device found")?;
+        let display = unsafe { Display::with_device(&device, None)? };
+
+        let template = ConfigTemplateBuilder::new()
+            .with_alpha_size(8)
+            .with_surface_type(ConfigSurfaceTypes::PBUFFER)
+            .build();
+        let config = unsafe { display.find_configs(template)? }
+            .next()
+            .ok_or("no EGL config supporting pbuffers found")?;
+
+        let context_attributes = ContextAttributesBuilder::new().build(None);
+        let fallback_context_attributes = ContextAttributesBuilder::new()
+            .with_context_api(ContextApi::Gles(None))
+            .build(None);
+        let context = unsafe {
+            display
+                .create_context(&config, &context_attributes)
+                .or_else(|_| display.create_context(&config, &fallback_context_attributes))?
+        };
+
+        // Prefer not having a surface at all, but not every EGL implementation
+        // supports surfaceless contexts, so fall back to a tiny pbuffer. All
+        // rendering goes to framebuffer objects anyway.
+        let (context, surface) = match context.make_current_surfaceless() {
+            Ok(context) => (context, None),
+            Err(_) => {
+                let context = unsafe {
+                    display
+                        .create_context(&config, &context_attributes)
+                        .or_else(|_| {
+                            display.create_context(&config, &fallback_context_attributes)
+                        })?
+                };
+                let one = NonZeroU32::new(1).unwrap();
+                let attrs = SurfaceAttributesBuilder::<PbufferSurface>::new().build(one, one);
+                let surface = unsafe { display.create_pbuffer_surface(&config, &attrs)? };
+                (context.make_current(&surface)?, Some(surface))
+            }
+        };
+
+        Ok(Self {
+            _context: context,
+            _surface: surface,
+            display,
+        })
+    }
+
+    pub fn display(&self) -> &Display {
+        &self.display
+    }
+}
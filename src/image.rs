@@ -0,0 +1,46 @@
+use std::{error::Error, fs::File, io::BufWriter, path::Path};
+
+/// An 8-bit RGBA image with rows stored from top to bottom.
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; 4 * width as usize * height as usize],
+        }
+    }
+
+    pub fn flip_vertically(&mut self) {
+        let row = 4 * self.width as usize;
+        let height = self.height as usize;
+        for y in 0..height / 2 {
+            let (top, bottom) = self.pixels.split_at_mut((height - 1 - y) * row);
+            top[y * row..(y + 1) * row].swap_with_slice(&mut bottom[..row]);
+        }
+    }
+
+    /// Copies `tile` into this image with its top-left corner at `x`, `y`.
+    pub fn paste(&mut self, tile: &Image, x: u32, y: u32) {
+        let row = 4 * tile.width as usize;
+        for (i, pixels) in tile.pixels.chunks_exact(row).enumerate() {
+            let start = 4 * ((y as usize + i) * self.width as usize + x as usize);
+            self.pixels[start..start + row].copy_from_slice(pixels);
+        }
+    }
+
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.pixels)?;
+        Ok(())
+    }
+}
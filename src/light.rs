@@ -1,7 +1,19 @@
-#[derive(Debug)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct SunLight {
+    #[serde(with = "crate::serde_glm::vec3")]
     pub direction: glm::Vec3,
+    #[serde(with = "crate::serde_glm::vec3")]
     pub color: glm::Vec3,
+    /// If set, `direction` is kept at this direction relative to the camera as
+    /// it moves.
+    #[serde(
+        with = "crate::serde_glm::option_vec3",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub camera_direction: Option<glm::Vec3>,
 }
 
 impl SunLight {
@@ -9,6 +21,13 @@ impl SunLight {
         Self {
             direction: glm::vec3(0.0, 0.0, -1.0),
             color: glm::vec3(1.0, 1.0, 1.0),
+            camera_direction: None,
         }
     }
 }
+
+impl Default for SunLight {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,15 +1,159 @@
+use std::error::Error;
+use std::path::Path;
+
+use clap::Parser;
 use winit::event_loop::EventLoop;
 
+use cli::{AnimateArgs, Cli, Command, ImageArgs, RenderArgs, ViewArgs};
+
+mod animation;
 mod app;
+mod background;
 mod camera;
+mod cli;
+mod colouring;
+mod cpu_renderer;
+mod fractal;
+mod hdr;
+#[cfg(not(apple))]
+mod headless;
+mod image;
 mod light;
-mod macros;
+mod quaternion;
+mod reference_orbit;
 mod renderer;
 mod scene;
+mod screenshot;
+mod serde_glm;
 mod shader;
 mod three_d;
+mod tiles;
+mod track;
 
 pub fn main() {
-    let event_loop = EventLoop::new().unwrap();
-    app::run_app(event_loop).unwrap();
+    let cli = Cli::parse();
+    let command = cli
+        .command
+        .unwrap_or_else(|| Command::View(ViewArgs::default()));
+    let result = match command {
+        Command::View(args) => view(args),
+        Command::Render(args) => render(args),
+        Command::Animate(args) => animate(args),
+        Command::Info => print_capabilities(),
+    };
+    if let Err(err) = result {
+        eprintln!("Error: {err}");
+        std::process::exit(1);
+    }
+}
+
+fn load_scene(path: Option<&Path>) -> Result<scene::Scene, Box<dyn Error>> {
+    match path {
+        Some(path) => scene::Scene::load(path)
+            .map_err(|err| format!("failed to load scene {}: {err}", path.display()).into()),
+        None => Ok(scene::Scene::init()),
+    }
+}
+
+fn view(args: ViewArgs) -> Result<(), Box<dyn Error>> {
+    let scene = load_scene(args.scene.as_deref())?;
+    let window = app::WindowOptions {
+        size: args.size,
+        fullscreen: args.fullscreen,
+        vsync: !args.no_vsync,
+    };
+    let shader_watcher = args
+        .watch_shaders
+        .then(|| shader::source::ShaderWatcher::new(shader::source::shader_dir()));
+    let event_loop = EventLoop::new()?;
+    app::run_app(event_loop, scene, window, shader_watcher)
+}
+
+fn render(args: RenderArgs) -> Result<(), Box<dyn Error>> {
+    let mut scene = load_scene(args.scene.as_deref())?;
+    scene.settle();
+    let (width, height, tile_size) = image_settings(&args.image, &scene);
+    let samples = args.image.samples;
+    let image = if args.image.cpu {
+        cpu_renderer::render(&scene, width, height, tile_size, samples, report_progress)
+    } else {
+        with_gpu_renderer(|renderer| {
+            renderer.render_tiled(&scene, width, height, tile_size, samples, report_progress)
+        })?
+    };
+    image.save_png(args.output)
+}
+
+fn animate(args: AnimateArgs) -> Result<(), Box<dyn Error>> {
+    let scene = load_scene(args.scene.as_deref())?;
+    let path = animation::CameraPath::load(&args.path, args.duration)
+        .map_err(|err| format!("failed to load camera path {}: {err}", args.path.display()))?;
+    let timing = animation::Timing {
+        fps: args.fps,
+        duration: args.duration.unwrap_or_else(|| path.duration()),
+    };
+    let (width, height, tile_size) = image_settings(&args.image, &scene);
+    let samples = args.image.samples;
+    if args.image.cpu {
+        animation::render_frames(&scene, &path, timing, &args.output, |scene| {
+            Ok(cpu_renderer::render(
+                scene,
+                width,
+                height,
+                tile_size,
+                samples,
+                |_, _| {},
+            ))
+        })
+    } else {
+        with_gpu_renderer(|renderer| {
+            animation::render_frames(&scene, &path, timing, &args.output, |scene| {
+                renderer.render_tiled(scene, width, height, tile_size, samples, |_, _| {})
+            })
+        })
+    }
+}
+
+/// The image and tile size from the command line, or else from the scene.
+fn image_settings(args: &ImageArgs, scene: &scene::Scene) -> (u32, u32, u32) {
+    let (width, height) = args
+        .size
+        .unwrap_or((scene.render.width, scene.render.height));
+    (
+        width,
+        height,
+        args.tile_size.unwrap_or(scene.render.tile_size),
+    )
+}
+
+fn print_capabilities() -> Result<(), Box<dyn Error>> {
+    with_gpu_renderer(|renderer| {
+        println!("{}", renderer.capabilities());
+        Ok(())
+    })
+}
+
+/// Runs `f` with a renderer on an offscreen GL context.
+#[cfg(not(apple))]
+fn with_gpu_renderer<T>(
+    f: impl FnOnce(&renderer::Renderer) -> Result<T, Box<dyn Error>>,
+) -> Result<T, Box<dyn Error>> {
+    let context = headless::HeadlessContext::new()?;
+    let renderer = renderer::Renderer::new(context.display())?;
+    f(&renderer)
+}
+
+#[cfg(apple)]
+fn with_gpu_renderer<T>(
+    _f: impl FnOnce(&renderer::Renderer) -> Result<T, Box<dyn Error>>,
+) -> Result<T, Box<dyn Error>> {
+    Err("offscreen GL rendering needs EGL, which is not available on this platform".into())
+}
+
+/// Shows how many of the tiles are done on one line of stderr.
+fn report_progress(done: usize, total: usize) {
+    eprint!("\rRendered {done}/{total} tiles");
+    if done == total {
+        eprintln!();
+    }
 }
@@ -0,0 +1,292 @@
+use std::ops::Mul;
+
+/// A rotation as a unit quaternion, in double precision like camera
+/// positions. glm has no quaternions, so this has just what the camera
+/// needs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quaternion {
+    pub w: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Quaternion {
+    pub const IDENTITY: Self = Self {
+        w: 1.0,
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+    };
+
+    /// The rotation by `angle` radians about `axis`, anticlockwise looking
+    /// from the tip of the axis.
+    pub fn from_axis_angle(axis: glm::DVec3, angle: f64) -> Self {
+        let axis = glm::normalize(axis) * (0.5 * angle).sin();
+        Self {
+            w: (0.5 * angle).cos(),
+            x: axis.x,
+            y: axis.y,
+            z: axis.z,
+        }
+    }
+
+    /// The rotation that turns -z towards `forward` and y as close to `up`
+    /// as it goes at right angles to `forward`. If `up` is parallel to
+    /// `forward` any direction at right angles is taken for it.
+    pub fn looking_along(forward: glm::DVec3, up: glm::DVec3) -> Self {
+        let back = -glm::normalize(forward);
+        let mut right = glm::cross(up, back);
+        if glm::length(right) < 1e-9 * glm::length(up) || glm::length(up) == 0.0 {
+            let axis = if back.x.abs() < 0.9 {
+                glm::dvec3(1.0, 0.0, 0.0)
+            } else {
+                glm::dvec3(0.0, 1.0, 0.0)
+            };
+            right = glm::cross(axis, back);
+        }
+        let right = glm::normalize(right);
+        let up = glm::cross(back, right);
+        Self::from_basis(right, up, back)
+    }
+
+    /// The rotation that takes x, y and z to the orthonormal `right`, `up`
+    /// and `back`.
+    fn from_basis(right: glm::DVec3, up: glm::DVec3, back: glm::DVec3) -> Self {
+        // Picks the largest component to divide by, which keeps it accurate
+        // for every rotation.
+        let trace = right.x + up.y + back.z;
+        let q = if trace > 0.0 {
+            let s = 2.0 * (trace + 1.0).sqrt();
+            Self {
+                w: 0.25 * s,
+                x: (up.z - back.y) / s,
+                y: (back.x - right.z) / s,
+                z: (right.y - up.x) / s,
+            }
+        } else if right.x > up.y && right.x > back.z {
+            let s = 2.0 * (1.0 + right.x - up.y - back.z).sqrt();
+            Self {
+                w: (up.z - back.y) / s,
+                x: 0.25 * s,
+                y: (up.x + right.y) / s,
+                z: (back.x + right.z) / s,
+            }
+        } else if up.y > back.z {
+            let s = 2.0 * (1.0 + up.y - right.x - back.z).sqrt();
+            Self {
+                w: (back.x - right.z) / s,
+                x: (up.x + right.y) / s,
+                y: 0.25 * s,
+                z: (back.y + up.z) / s,
+            }
+        } else {
+            let s = 2.0 * (1.0 + back.z - right.x - up.y).sqrt();
+            Self {
+                w: (right.y - up.x) / s,
+                x: (back.x + right.z) / s,
+                y: (back.y + up.z) / s,
+                z: 0.25 * s,
+            }
+        };
+        q.normalize()
+    }
+
+    pub fn dot(&self, other: &Self) -> f64 {
+        self.w * other.w + self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    /// Scaled back to unit length, which rounding errors wear away from over
+    /// many rotations.
+    pub fn normalize(&self) -> Self {
+        let length = self.dot(self).sqrt();
+        Self {
+            w: self.w / length,
+            x: self.x / length,
+            y: self.y / length,
+            z: self.z / length,
+        }
+    }
+
+    pub fn rotate(&self, v: glm::DVec3) -> glm::DVec3 {
+        let axis = glm::dvec3(self.x, self.y, self.z);
+        let t = glm::cross(axis, v) * 2.0;
+        v + t * self.w + glm::cross(axis, t)
+    }
+
+    /// The angle in radians of the smallest rotation from `self` to `other`.
+    pub fn angle_to(&self, other: &Self) -> f64 {
+        2.0 * self.dot(other).abs().min(1.0).acos()
+    }
+
+    /// Turns from `self` towards `other` at a steady rate the short way
+    /// round, a fraction `t` of the angle between them.
+    pub fn slerp(&self, other: &Self, t: f64) -> Self {
+        let mut cos_half = self.dot(other);
+        let mut other = *other;
+        if cos_half < 0.0 {
+            // `-other` is the same rotation, a shorter way round.
+            cos_half = -cos_half;
+            other = Self {
+                w: -other.w,
+                x: -other.x,
+                y: -other.y,
+                z: -other.z,
+            };
+        }
+        let (a, b) = if cos_half > 0.9999 {
+            (1.0 - t, t)
+        } else {
+            let half = cos_half.acos();
+            let sin_half = half.sin();
+            (
+                ((1.0 - t) * half).sin() / sin_half,
+                (t * half).sin() / sin_half,
+            )
+        };
+        Self {
+            w: a * self.w + b * other.w,
+            x: a * self.x + b * other.x,
+            y: a * self.y + b * other.y,
+            z: a * self.z + b * other.z,
+        }
+        .normalize()
+    }
+}
+
+/// `a * b` rotates by `b` first and then by `a`.
+impl Mul for Quaternion {
+    type Output = Self;
+
+    fn mul(self, b: Self) -> Self {
+        let a = self;
+        Self {
+            w: a.w * b.w - a.x * b.x - a.y * b.y - a.z * b.z,
+            x: a.w * b.x + a.x * b.w + a.y * b.z - a.z * b.y,
+            y: a.w * b.y - a.x * b.z + a.y * b.w + a.z * b.x,
+            z: a.w * b.z + a.x * b.y - a.y * b.x + a.z * b.w,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glm::{dvec3, DVec3};
+
+    use super::*;
+
+    fn close(a: DVec3, b: DVec3) -> bool {
+        glm::length(a - b) < 1e-12
+    }
+
+    /// Rotations about assorted axes, including half turns, which are the
+    /// ones `from_basis` can not divide by `w` for.
+    fn rotations() -> Vec<Quaternion> {
+        let axes = [
+            dvec3(1.0, 0.0, 0.0),
+            dvec3(0.0, 1.0, 0.0),
+            dvec3(0.0, 0.0, 1.0),
+            dvec3(1.0, -2.0, 0.5),
+            dvec3(-0.3, 0.2, 1.0),
+        ];
+        let angles = [0.0, 0.4, -1.3, 2.5, std::f64::consts::PI];
+        axes.iter()
+            .flat_map(|&axis| {
+                angles
+                    .iter()
+                    .map(move |&angle| Quaternion::from_axis_angle(axis, angle))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn from_basis_round_trips() {
+        for q in rotations() {
+            let (right, up, back) = (
+                q.rotate(dvec3(1.0, 0.0, 0.0)),
+                q.rotate(dvec3(0.0, 1.0, 0.0)),
+                q.rotate(dvec3(0.0, 0.0, 1.0)),
+            );
+            let rebuilt = Quaternion::from_basis(right, up, back);
+            assert!(close(rebuilt.rotate(dvec3(1.0, 0.0, 0.0)), right), "{q:?}");
+            assert!(close(rebuilt.rotate(dvec3(0.0, 1.0, 0.0)), up), "{q:?}");
+            assert!(close(rebuilt.rotate(dvec3(0.0, 0.0, 1.0)), back), "{q:?}");
+        }
+    }
+
+    #[test]
+    fn from_basis_covers_every_branch() {
+        // The identity and half turns about x, y and z, which make the trace
+        // and then each diagonal element of the matrix the largest.
+        let x = dvec3(1.0, 0.0, 0.0);
+        let y = dvec3(0.0, 1.0, 0.0);
+        let z = dvec3(0.0, 0.0, 1.0);
+        for (right, up, back) in [(x, y, z), (x, -y, -z), (-x, y, -z), (-x, -y, z)] {
+            let q = Quaternion::from_basis(right, up, back);
+            assert!(close(q.rotate(x), right));
+            assert!(close(q.rotate(y), up));
+            assert!(close(q.rotate(z), back));
+        }
+    }
+
+    #[test]
+    fn looking_along_faces_forward_with_up_on_top() {
+        let cases = [
+            (dvec3(0.0, 0.0, -1.0), dvec3(0.0, 1.0, 0.0)),
+            (dvec3(3.0, -1.0, 0.5), dvec3(0.0, 1.0, 0.0)),
+            (dvec3(0.0, -2.0, 0.0), dvec3(0.0, 0.0, -1.0)),
+            (dvec3(0.0, 0.0, 1.0), dvec3(1.0, 1.0, 0.0)),
+        ];
+        for (forward, up) in cases {
+            let q = Quaternion::looking_along(forward, up);
+            let top = q.rotate(dvec3(0.0, 1.0, 0.0));
+            assert!(close(
+                q.rotate(dvec3(0.0, 0.0, -1.0)),
+                glm::normalize(forward)
+            ));
+            assert!(glm::dot(top, forward).abs() < 1e-12);
+            assert!(glm::dot(top, up) > 0.0);
+        }
+    }
+
+    #[test]
+    fn looking_along_up_is_still_a_rotation() {
+        let forward = dvec3(0.0, 1.0, 0.0);
+        let q = Quaternion::looking_along(forward, dvec3(0.0, 1.0, 0.0));
+        assert!((q.dot(&q) - 1.0).abs() < 1e-12);
+        assert!(close(q.rotate(dvec3(0.0, 0.0, -1.0)), forward));
+    }
+
+    #[test]
+    fn slerp_turns_steadily_the_short_way() {
+        let a = Quaternion::from_axis_angle(dvec3(0.0, 1.0, 0.0), 0.3);
+        let b = Quaternion::from_axis_angle(dvec3(1.0, 1.0, 0.0), 2.0);
+        let angle = a.angle_to(&b);
+        assert!(a.slerp(&b, 0.0).angle_to(&a) < 1e-9);
+        assert!(a.slerp(&b, 1.0).angle_to(&b) < 1e-9);
+        for t in [0.25, 0.5, 0.9] {
+            let between = a.slerp(&b, t);
+            assert!((a.angle_to(&between) - t * angle).abs() < 1e-9);
+            assert!((between.angle_to(&b) - (1.0 - t) * angle).abs() < 1e-9);
+        }
+
+        // The same rotation as `b` with the opposite sign.
+        let minus_b = Quaternion {
+            w: -b.w,
+            x: -b.x,
+            y: -b.y,
+            z: -b.z,
+        };
+        assert!(a.slerp(&minus_b, 0.5).angle_to(&a.slerp(&b, 0.5)) < 1e-9);
+    }
+
+    #[test]
+    fn product_rotates_by_the_right_factor_first() {
+        let v = dvec3(0.3, -1.0, 2.0);
+        for a in rotations() {
+            for b in rotations() {
+                assert!(close((a * b).rotate(v), a.rotate(b.rotate(v))));
+            }
+        }
+    }
+}
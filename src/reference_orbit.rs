@@ -0,0 +1,97 @@
+use glm::{asin, atan, clamp_s, cos, dot, dvec3, length, pow, sin, DVec3};
+
+use crate::{fractal::Formula, scene::Scene};
+
+/// The orbit of a reference point at the camera's focus, iterated in double
+/// precision for the deep zoom mode. `perturbation.glsl` follows it with the
+/// small difference of each pixel's orbit from it, which keeps far more
+/// detail than iterating the pixel's own `f32` position.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReferenceOrbit {
+    /// The reference point in double precision.
+    pub focus: DVec3,
+    /// The camera position minus the reference point.
+    pub offset: glm::Vec3,
+    /// The reference point and the orbit from it, until it escapes, the
+    /// iterations run out or it lands on the z axis.
+    pub points: Vec<glm::Vec3>,
+}
+
+impl ReferenceOrbit {
+    /// The orbit for the current view, or `None` if deep zoom is off or the
+    /// formula is not one the shader can follow.
+    pub fn new(scene: &Scene) -> Option<Self> {
+        if !scene.render.deep_zoom {
+            return None;
+        }
+        let camera = &scene.camera;
+        let fractal = &scene.fractal;
+        let center = camera.position + camera.state().forward();
+        let points = match fractal.formula {
+            Formula::Mandelbulb => bulb_orbit(
+                center,
+                fractal.power as f64,
+                fractal.phase as f64,
+                fractal.bulb_iterations,
+            ),
+            Formula::Mandelbox => box_orbit(
+                center,
+                fractal.scale as f64,
+                fractal.min_rad2 as f64,
+                fractal.box_iterations,
+            ),
+            Formula::RoundBox | Formula::Sphere => return None,
+        };
+        Some(Self {
+            focus: center,
+            offset: -camera.forward(),
+            points: points.into_iter().map(glm::to_vec3).collect(),
+        })
+    }
+
+    /// The number of iterations a pixel can follow the orbit for.
+    pub fn len(&self) -> usize {
+        self.points.len() - 1
+    }
+}
+
+/// The Mandelbulb iteration of `mandel` in `distance.glsl`, starting from and
+/// adding `c`. It stops on the z axis, where the angle around it is undefined
+/// and the rest of the orbit would be NaN, so pixels rebase instead of
+/// following it.
+fn bulb_orbit(c: DVec3, power: f64, phase: f64, iterations: u32) -> Vec<DVec3> {
+    let mut z = c;
+    let mut points = vec![z];
+    for _ in 0..iterations {
+        let r = length(z);
+        if r > 2.0 {
+            break;
+        }
+        let theta = atan(z.y / z.x) * power;
+        let phi = (asin(z.z / r) + phase) * power;
+        z = dvec3(cos(theta) * cos(phi), sin(theta) * cos(phi), sin(phi)) * pow(r, power) + c;
+        if !(z.x.is_finite() && z.y.is_finite() && z.z.is_finite()) {
+            break;
+        }
+        points.push(z);
+    }
+    points
+}
+
+/// The Mandelbox iteration of `mandelbox` in `distance.glsl`, starting from
+/// and adding `c`.
+fn box_orbit(c: DVec3, scale: f64, min_rad2: f64, iterations: u32) -> Vec<DVec3> {
+    let mut z = c;
+    let mut points = vec![z];
+    for _ in 0..iterations {
+        let folded = clamp_s(z, -1.0, 1.0) * 2.0 - z;
+        let r2 = dot(folded, folded);
+        let k = (min_rad2 / r2).max(min_rad2).clamp(0.0, 1.0);
+        z = folded * (k * scale / min_rad2) + c;
+        points.push(z);
+        if r2 > 1000.0 {
+            break;
+        }
+    }
+    points
+}
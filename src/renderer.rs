@@ -1,7 +1,4 @@
-use std::{
-    ops::Deref,
-    ptr::{null, null_mut},
-};
+use std::{cell::RefCell, error::Error, fmt, ops::Deref, ptr::null, sync::Arc};
 
 use gl::types::GLfloat;
 use glutin::prelude::GlDisplay;
@@ -9,7 +6,22 @@ use std::ffi::{CStr, CString};
 
 use glm;
 
-use crate::{camera::Camera, light::SunLight};
+use crate::{
+    background::{Background, EnvironmentMap},
+    camera::sample_offset,
+    colouring::{Colouring, Palette, PALETTE_SIZE},
+    fractal::Fractal,
+    hdr::HdrImage,
+    image::Image,
+    reference_orbit::ReferenceOrbit,
+    scene::Scene,
+    shader::{
+        shading::{AtmosphereWeights, ShadingWeights},
+        source::ShaderSource,
+        version::GlslVersion,
+    },
+    tiles::Tile,
+};
 
 pub mod gl {
     #![allow(clippy::all)]
@@ -18,28 +30,357 @@ pub mod gl {
     pub use Gles2 as Gl;
 }
 
+#[derive(Debug, Clone, Copy)]
+pub enum ShaderStage {
+    Vertex,
+    Fragment,
+}
+
+impl fmt::Display for ShaderStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShaderStage::Vertex => write!(f, "vertex"),
+            ShaderStage::Fragment => write!(f, "fragment"),
+        }
+    }
+}
+
+/// Why the shader program could not be built.
+#[derive(Debug)]
+pub enum RendererError {
+    /// A shader failed to compile. For the fragment shader the log has its
+    /// locations mapped back to the source files.
+    Compile {
+        stage: ShaderStage,
+        log: String,
+    },
+    Link(String),
+    /// The linked program has no active attribute with this name.
+    MissingAttribute(&'static str),
+    /// The linked program has no uniform block with this name.
+    MissingUniformBlock(&'static str),
+    /// The context's `GL_VERSION` has no GLSL dialect the shaders are written
+    /// for.
+    UnsupportedVersion(String),
+}
+
+impl fmt::Display for RendererError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RendererError::Compile { stage, log } => {
+                write!(
+                    f,
+                    "failed to compile the {stage} shader:\n{}",
+                    log.trim_end()
+                )
+            }
+            RendererError::Link(log) => {
+                write!(f, "failed to link the shader program:\n{}", log.trim_end())
+            }
+            RendererError::MissingAttribute(name) => {
+                write!(f, "shader program has no attribute `{name}`")
+            }
+            RendererError::MissingUniformBlock(name) => {
+                write!(f, "shader program has no uniform block `{name}`")
+            }
+            RendererError::UnsupportedVersion(version) => {
+                write!(f, "no shaders for OpenGL version {version}, GLES needs 3.0")
+            }
+        }
+    }
+}
+
+impl Error for RendererError {}
+
+/// What the GL implementation the renderer runs on supports.
+#[derive(Debug)]
+pub struct Capabilities {
+    pub vendor: String,
+    pub renderer: String,
+    pub version: String,
+    pub shading_language_version: String,
+    /// The dialect the shaders are compiled as.
+    pub glsl: GlslVersion,
+    pub max_texture_size: i32,
+    pub max_viewport_dims: [i32; 2],
+    /// The format jittered samples are averaged in, or `None` if the context
+    /// can only draw one sample per pixel.
+    pub accumulation_format: Option<gl::types::GLenum>,
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let accumulation = match self.accumulation_format {
+            Some(gl::RGBA32F) => "RGBA32F",
+            Some(gl::RGBA16F) => "RGBA16F",
+            Some(_) => "RGBA8",
+            None => "unavailable",
+        };
+        let uniform_blocks = if self.glsl.uses_uniform_block() {
+            "yes"
+        } else {
+            "no"
+        };
+        let [viewport_width, viewport_height] = self.max_viewport_dims;
+        writeln!(f, "Vendor:               {}", self.vendor)?;
+        writeln!(f, "Renderer:             {}", self.renderer)?;
+        writeln!(f, "OpenGL version:       {}", self.version)?;
+        writeln!(f, "GLSL version:         {}", self.shading_language_version)?;
+        writeln!(f, "Shaders compiled as:  {:?}", self.glsl)?;
+        writeln!(f, "Uniform blocks:       {uniform_blocks}")?;
+        writeln!(f, "Max texture size:     {}", self.max_texture_size)?;
+        writeln!(
+            f,
+            "Max viewport:         {viewport_width}x{viewport_height}"
+        )?;
+        write!(f, "Sample accumulation:  {accumulation}")
+    }
+}
+
 pub struct Renderer {
+    version: GlslVersion,
     program: gl::types::GLuint,
+    /// Shows the samples averaged in a framebuffer, see `present`.
+    present_program: gl::types::GLuint,
+    formats: FormatSupport,
     vao: gl::types::GLuint,
     vbo: gl::types::GLuint,
     ray_bo: gl::types::GLuint,
     uniform_bo: gl::types::GLuint,
+    palette_texture: gl::types::GLuint,
+    environment_texture: gl::types::GLuint,
+    irradiance_texture: gl::types::GLuint,
+    reference_texture: gl::types::GLuint,
+    /// The environment currently in the textures, so that it is only
+    /// uploaded when the scene switches to another one.
+    uploaded_environment: RefCell<Option<Arc<EnvironmentMap>>>,
+    /// The palette currently in its texture, so that it is only baked and
+    /// uploaded when the scene changes it.
+    uploaded_palette: RefCell<Option<Palette>>,
+    /// The focus and fractal of the reference orbit currently in its
+    /// texture, which are all the orbit depends on.
+    uploaded_reference: RefCell<Option<(glm::DVec3, Fractal)>>,
+    accumulation: Option<Accumulation>,
     gl: gl::Gl,
 }
 
+/// Which of `ACCUMULATION_FORMATS` the context can average samples in, and
+/// whether it can filter the 32-bit float one when scaling it up.
+struct FormatSupport {
+    blendable: Vec<gl::types::GLenum>,
+    filterable_float32: bool,
+}
+
+/// The running average of jittered samples of the same frame, which is what
+/// the window shows.
+struct Accumulation {
+    framebuffer: Framebuffer,
+    width: i32,
+    height: i32,
+    samples: u32,
+    /// What the samples were drawn from, they are thrown away when it
+    /// changes.
+    inputs: Option<FrameInputs>,
+}
+
+/// Everything a frame is drawn from.
+#[derive(PartialEq)]
+struct FrameInputs {
+    size: (i32, i32),
+    program: gl::types::GLuint,
+    uniforms: UniformData,
+    corners: [glm::Vec3; 4],
+    palette: Palette,
+    repeat: bool,
+}
+
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct UniformData {
     origin: glm::Vec3,
     _0: i32,
-    light_dir: glm::Vec3,
+    origin_low: glm::Vec3,
     _1: i32,
+    light_dir: glm::Vec3,
+    _2: i32,
     light_color: glm::Vec3,
     stop_distance: f32,
+    surface_color: glm::Vec3,
+    power: f32,
+    phase: f32,
+    formula: i32,
+    bulb_iterations: i32,
+    box_scale: f32,
+    min_rad2: f32,
+    box_iterations: i32,
+    max_steps: i32,
+    diffuse: f32,
+    ambient: f32,
+    specular: f32,
+    shininess: f32,
+    rim: f32,
+    rim_power: f32,
+    shadow_sharpness: f32,
+    shadow_steps: i32,
+    occlusion_strength: f32,
+    occlusion_radius: f32,
+    occlusion_samples: i32,
+    step_occlusion_strength: f32,
+    colour_mode: i32,
+    trap_shape: i32,
+    palette_scale: f32,
+    palette_offset: f32,
+    _3: i32,
+    trap_vector: glm::Vec3,
+    trap_scalar: f32,
+    background_top: glm::Vec3,
+    background_type: i32,
+    background_horizon: glm::Vec3,
+    exposure: f32,
+    background_bottom: glm::Vec3,
+    reflection: f32,
+    glow: glm::Vec3,
+    fog_density: f32,
+    fog_falloff: f32,
+    fog_height: f32,
+    deep_zoom: i32,
+    reference_length: i32,
+    reference_offset: glm::Vec3,
+    glow_depth: f32,
+}
+
+impl UniformData {
+    pub(crate) fn new(scene: &Scene, reference: Option<&ReferenceOrbit>) -> Self {
+        let camera = &scene.camera;
+        let light = &scene.light;
+        let weights = ShadingWeights::new(&scene.shading);
+        let colouring = &scene.colouring;
+        let (trap_shape, trap_vector, trap_scalar) = colouring.trap.shape.uniforms();
+        let (background_type, background_top, background_horizon, background_bottom, exposure) =
+            scene.background.uniforms();
+        let atmosphere = AtmosphereWeights::new(&scene.atmosphere, camera.focal_distance as f32);
+        let (origin, origin_low) = camera.split_position();
+        Self {
+            origin,
+            origin_low,
+            light_dir: light.direction,
+            light_color: light.color,
+            stop_distance: camera.get_stop_distance(),
+            surface_color: colouring.color,
+            power: scene.fractal.power,
+            phase: scene.fractal.phase,
+            formula: scene.fractal.formula as i32,
+            bulb_iterations: scene.fractal.bulb_iterations as i32,
+            box_scale: scene.fractal.scale,
+            min_rad2: scene.fractal.min_rad2,
+            box_iterations: scene.fractal.box_iterations as i32,
+            max_steps: scene.render.max_steps as i32,
+            diffuse: weights.diffuse,
+            ambient: weights.ambient,
+            specular: weights.specular,
+            shininess: scene.shading.shininess,
+            rim: weights.rim,
+            rim_power: scene.shading.rim_power,
+            shadow_sharpness: scene.shading.shadow_sharpness,
+            shadow_steps: weights.shadow_steps as i32,
+            occlusion_strength: weights.occlusion,
+            occlusion_radius: scene.shading.occlusion_radius * camera.focal_distance as f32,
+            occlusion_samples: weights.occlusion_samples as i32,
+            step_occlusion_strength: weights.step_occlusion,
+            colour_mode: colouring.trap.mode as i32,
+            trap_shape,
+            palette_scale: colouring.scale,
+            palette_offset: colouring.offset,
+            trap_vector,
+            trap_scalar,
+            background_top,
+            background_type,
+            background_horizon,
+            exposure,
+            background_bottom,
+            reflection: weights.reflection,
+            glow: atmosphere.glow,
+            fog_density: atmosphere.fog_density,
+            fog_falloff: atmosphere.fog_falloff,
+            fog_height: atmosphere.fog_height,
+            deep_zoom: reference.is_some() as i32,
+            reference_length: reference.map_or(0, |reference| reference.len() as i32),
+            reference_offset: reference
+                .map_or(glm::vec3(0.0, 0.0, 0.0), |reference| reference.offset),
+            glow_depth: atmosphere.glow_depth,
+            _0: 0,
+            _1: 0,
+            _2: 0,
+            _3: 0,
+        }
+    }
+
+    /// The fields with their names in the shader, for contexts without
+    /// uniform blocks.
+    fn values(&self) -> [(&'static CStr, UniformValue); 46] {
+        use UniformValue::*;
+        [
+            (c"origin", Vec3(self.origin)),
+            (c"origin_low", Vec3(self.origin_low)),
+            (c"light_dir", Vec3(self.light_dir)),
+            (c"light_color", Vec3(self.light_color)),
+            (c"stop_distance", Float(self.stop_distance)),
+            (c"surface_color", Vec3(self.surface_color)),
+            (c"power", Float(self.power)),
+            (c"phase", Float(self.phase)),
+            (c"formula", Int(self.formula)),
+            (c"bulb_iterations", Int(self.bulb_iterations)),
+            (c"box_scale", Float(self.box_scale)),
+            (c"min_rad2", Float(self.min_rad2)),
+            (c"box_iterations", Int(self.box_iterations)),
+            (c"max_steps", Int(self.max_steps)),
+            (c"diffuse", Float(self.diffuse)),
+            (c"ambient", Float(self.ambient)),
+            (c"specular", Float(self.specular)),
+            (c"shininess", Float(self.shininess)),
+            (c"rim", Float(self.rim)),
+            (c"rim_power", Float(self.rim_power)),
+            (c"shadow_sharpness", Float(self.shadow_sharpness)),
+            (c"shadow_steps", Int(self.shadow_steps)),
+            (c"occlusion_strength", Float(self.occlusion_strength)),
+            (c"occlusion_radius", Float(self.occlusion_radius)),
+            (c"occlusion_samples", Int(self.occlusion_samples)),
+            (
+                c"step_occlusion_strength",
+                Float(self.step_occlusion_strength),
+            ),
+            (c"colour_mode", Int(self.colour_mode)),
+            (c"trap_shape", Int(self.trap_shape)),
+            (c"palette_scale", Float(self.palette_scale)),
+            (c"palette_offset", Float(self.palette_offset)),
+            (c"trap_vector", Vec3(self.trap_vector)),
+            (c"trap_scalar", Float(self.trap_scalar)),
+            (c"background_top", Vec3(self.background_top)),
+            (c"background_type", Int(self.background_type)),
+            (c"background_horizon", Vec3(self.background_horizon)),
+            (c"exposure", Float(self.exposure)),
+            (c"background_bottom", Vec3(self.background_bottom)),
+            (c"reflection", Float(self.reflection)),
+            (c"glow", Vec3(self.glow)),
+            (c"fog_density", Float(self.fog_density)),
+            (c"fog_falloff", Float(self.fog_falloff)),
+            (c"fog_height", Float(self.fog_height)),
+            (c"deep_zoom", Int(self.deep_zoom)),
+            (c"reference_length", Int(self.reference_length)),
+            (c"reference_offset", Vec3(self.reference_offset)),
+            (c"glow_depth", Float(self.glow_depth)),
+        ]
+    }
+}
+
+enum UniformValue {
+    Float(f32),
+    Int(i32),
+    Vec3(glm::Vec3),
 }
 
 impl Renderer {
-    pub fn new<D: GlDisplay>(gl_display: &D) -> Self {
+    pub fn new<D: GlDisplay>(gl_display: &D) -> Result<Self, RendererError> {
         println!("Creating OpenGL stuff...");
         unsafe {
             let gl = gl::Gl::load_with(|symbol| {
@@ -58,34 +399,24 @@ impl Renderer {
                 println!("Shaders version on {}", shaders_version.to_string_lossy());
             }
 
-            // Compile shader program
-
-            let vertex_shader = create_shader(&gl, gl::VERTEX_SHADER, VERTEX_SHADER_SOURCE);
-            let fragment_shader = create_shader(
-                &gl,
-                gl::FRAGMENT_SHADER,
-                FRAGMENT_SHADER_SOURCE.to_bytes_with_nul(),
-            );
-
-            let program = gl.CreateProgram();
+            let version = match get_gl_string(&gl, gl::VERSION) {
+                Some(version) => {
+                    let version = version.to_string_lossy();
+                    GlslVersion::detect(&version)
+                        .ok_or_else(|| RendererError::UnsupportedVersion(version.into_owned()))?
+                }
+                None => GlslVersion::Legacy120,
+            };
 
-            gl.AttachShader(program, vertex_shader);
-            gl.AttachShader(program, fragment_shader);
-
-            gl.LinkProgram(program);
+            // Compile shader program
 
+            let program =
+                create_program(&gl, version, &ShaderSource::embedded(ShaderStage::Fragment))?;
+            let present_program = create_present_program(&gl, version)?;
             gl.UseProgram(program);
 
-            gl.DeleteShader(vertex_shader);
-            gl.DeleteShader(fragment_shader);
-
             println!("Compiled shaders.");
 
-            let pos_attrib = gl.GetAttribLocation(program, b"position\0".as_ptr() as *const _);
-            let ray_attrib = gl.GetAttribLocation(program, b"ray\0".as_ptr() as *const _);
-            let uniform_attrib = gl.GetUniformBlockIndex(program, b"uni\0".as_ptr() as *const _);
-            gl.UniformBlockBinding(program, uniform_attrib, 0);
-
             // This is for vertex indices
             let mut vao = std::mem::zeroed();
             gl.GenVertexArrays(1, &mut vao);
@@ -102,15 +433,8 @@ impl Renderer {
                 VERTEX_DATA.as_ptr() as *const _,
                 gl::STATIC_DRAW,
             );
-            gl.VertexAttribPointer(
-                pos_attrib as gl::types::GLuint,
-                2,
-                gl::FLOAT,
-                0,
-                0,
-                std::ptr::null(),
-            );
-            gl.EnableVertexAttribArray(pos_attrib as gl::types::GLuint);
+            gl.VertexAttribPointer(POSITION_ATTRIB, 2, gl::FLOAT, 0, 0, std::ptr::null());
+            gl.EnableVertexAttribArray(POSITION_ATTRIB);
 
             // Create ray direction buffer
             let mut ray_bo = std::mem::zeroed();
@@ -122,69 +446,256 @@ impl Renderer {
                 null(),
                 gl::DYNAMIC_DRAW,
             );
-            gl.VertexAttribPointer(
-                ray_attrib as gl::types::GLuint,
-                3,
-                gl::FLOAT,
+            gl.VertexAttribPointer(RAY_ATTRIB, 3, gl::FLOAT, 1, 0, std::ptr::null());
+            gl.EnableVertexAttribArray(RAY_ATTRIB);
+
+            // Create uniform buffer, unless the uniforms are set one by one
+            let mut uniform_bo = 0;
+            if version.uses_uniform_block() {
+                gl.GenBuffers(1, &mut uniform_bo);
+                gl.BindBuffer(gl::UNIFORM_BUFFER, uniform_bo);
+                gl.BufferData(
+                    gl::UNIFORM_BUFFER,
+                    std::mem::size_of::<UniformData>() as isize,
+                    null(),
+                    gl::DYNAMIC_DRAW,
+                );
+                gl.BindBufferRange(
+                    gl::UNIFORM_BUFFER,
+                    UNIFORM_BINDING,
+                    uniform_bo,
+                    0,
+                    std::mem::size_of::<UniformData>() as isize,
+                );
+            }
+
+            // Create palette texture, filled in when the palette changes
+            let mut palette_texture = 0;
+            gl.GenTextures(1, &mut palette_texture);
+            gl.BindTexture(gl::TEXTURE_2D, palette_texture);
+            gl.TexImage2D(
+                gl::TEXTURE_2D,
+                0,
+                gl::RGBA8 as i32,
+                PALETTE_SIZE as i32,
                 1,
                 0,
-                std::ptr::null(),
-            );
-            gl.EnableVertexAttribArray(ray_attrib as gl::types::GLuint);
-
-            // Create uniform buffer
-            let mut uniform_bo = std::mem::zeroed();
-            gl.GenBuffers(1, &mut uniform_bo);
-            gl.BindBuffer(gl::UNIFORM_BUFFER, uniform_bo);
-            gl.BufferData(
-                gl::UNIFORM_BUFFER,
-                std::mem::size_of::<UniformData>() as isize,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
                 null(),
-                gl::DYNAMIC_DRAW,
-            );
-            gl.BindBufferRange(
-                gl::UNIFORM_BUFFER,
-                uniform_attrib as gl::types::GLuint,
-                uniform_bo,
-                0,
-                std::mem::size_of::<UniformData>() as isize,
             );
+            gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+            gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+            gl.BindTexture(gl::TEXTURE_2D, 0);
 
-            Self {
+            // Create environment textures, filled in when a scene has one
+            let environment_texture = create_float_texture(&gl);
+            let irradiance_texture = create_float_texture(&gl);
+
+            // Create reference orbit texture, filled in when the orbit changes
+            let mut reference_texture = 0;
+            gl.GenTextures(1, &mut reference_texture);
+            gl.BindTexture(gl::TEXTURE_2D, reference_texture);
+            gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+            gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+            gl.BindTexture(gl::TEXTURE_2D, 0);
+
+            Ok(Self {
+                version,
                 program,
+                present_program,
+                formats: FormatSupport::query(&gl, version),
                 vao,
                 vbo,
                 ray_bo,
                 uniform_bo,
+                palette_texture,
+                environment_texture,
+                irradiance_texture,
+                reference_texture,
+                uploaded_environment: RefCell::new(None),
+                uploaded_palette: RefCell::new(None),
+                uploaded_reference: RefCell::new(None),
+                accumulation: None,
                 gl,
-            }
+            })
         }
     }
 
-    pub fn draw(&self, camera: &Camera, light: &SunLight) {
-        self.draw_with_clear_color(camera, light, 0.1, 0.1, 0.1, 0.9);
+    /// Compiles and links a new fragment shader and switches to it. If that
+    /// fails the current program stays in use.
+    pub fn reload_fragment_shader(&mut self, source: &ShaderSource) -> Result<(), RendererError> {
+        let program = unsafe { create_program(&self.gl, self.version, source)? };
+        unsafe {
+            self.gl.DeleteProgram(self.program);
+        }
+        self.program = program;
+        Ok(())
+    }
+
+    pub fn draw(&self, scene: &Scene) {
+        self.draw_with_clear_color(scene, 0.1, 0.1, 0.1, 0.9);
     }
 
     pub fn draw_with_clear_color(
         &self,
-        camera: &Camera,
-        light: &SunLight,
+        scene: &Scene,
         red: GLfloat,
         green: GLfloat,
         blue: GLfloat,
         alpha: GLfloat,
     ) {
-        let corners = camera.get_corners();
-        let uniform_data = [UniformData {
-            origin: camera.position,
-            light_dir: light.direction,
-            light_color: light.color,
-            stop_distance: camera.get_stop_distance(),
-            _0: 0,
-            _1: 0,
-        }];
+        unsafe {
+            self.gl.ClearColor(red, green, blue, alpha);
+            self.gl.Clear(gl::COLOR_BUFFER_BIT);
+        }
+        let reference = ReferenceOrbit::new(scene);
+        self.draw_frame(scene, reference.as_ref(), scene.camera.get_corners());
+    }
 
+    /// Adds another jittered sample of `scene` to the ones drawn before and
+    /// shows their average in the viewport. The samples start over when
+    /// anything the frame is drawn from changes, and no more are taken once
+    /// there are `max_samples`. Returns the number of samples in the average.
+    ///
+    /// With a `scale` below 1 the samples are taken at that fraction of the
+    /// viewport size and scaled up to fill it.
+    ///
+    /// Contexts that can not average samples draw a single sample straight
+    /// into the viewport, at full resolution whatever the `scale` as there is
+    /// nothing to scale up from, and return `max_samples` so the frame counts
+    /// as finished.
+    pub fn draw_accumulated(&mut self, scene: &Scene, max_samples: u32, scale: f32) -> u32 {
+        let mut viewport = [0; 4];
         unsafe {
+            self.gl.GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+        }
+        let [_, _, width, height] = viewport;
+
+        let mut accumulation = match self.accumulation.take() {
+            Some(accumulation) if accumulation.width == width && accumulation.height == height => {
+                accumulation
+            }
+            stale => {
+                if let Some(accumulation) = stale {
+                    unsafe { accumulation.framebuffer.delete(&self.gl) };
+                }
+                match unsafe { Accumulation::new(&self.gl, &self.formats, width, height) } {
+                    Some(accumulation) => accumulation,
+                    None => {
+                        self.draw(scene);
+                        return max_samples;
+                    }
+                }
+            }
+        };
+
+        let size = (
+            ((width as f32 * scale).round() as i32).clamp(1, width),
+            ((height as f32 * scale).round() as i32).clamp(1, height),
+        );
+        let reference = ReferenceOrbit::new(scene);
+        let inputs = FrameInputs {
+            size,
+            program: self.program,
+            uniforms: UniformData::new(scene, reference.as_ref()),
+            corners: scene.camera.get_corners(),
+            palette: scene.colouring.palette.clone(),
+            repeat: scene.colouring.repeat,
+        };
+        if accumulation.inputs.as_ref() != Some(&inputs) {
+            accumulation.samples = 0;
+            accumulation.inputs = Some(inputs);
+        }
+
+        unsafe {
+            if accumulation.samples < max_samples.max(1) {
+                let offset = sample_offset(accumulation.samples);
+                let corners =
+                    scene
+                        .camera
+                        .get_jittered_corners(offset, size.0 as f32, size.1 as f32);
+
+                accumulation.framebuffer.bind(&self.gl);
+                self.gl.Viewport(0, 0, size.0, size.1);
+                self.draw_sample(scene, reference.as_ref(), corners, accumulation.samples);
+                accumulation.samples += 1;
+            }
+
+            self.gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
+            self.gl
+                .Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+            self.present(&accumulation.framebuffer, size, (width, height));
+        }
+
+        let samples = accumulation.samples;
+        self.accumulation = Some(accumulation);
+        samples
+    }
+
+    /// Draws sample `i` of a frame into the bound framebuffer, which holds the
+    /// average of the samples before it. Blending the new sample in with a
+    /// weight of 1 / (i + 1) keeps the average of all of them.
+    unsafe fn draw_sample(
+        &self,
+        scene: &Scene,
+        reference: Option<&ReferenceOrbit>,
+        corners: [glm::Vec3; 4],
+        i: u32,
+    ) {
+        if i > 0 {
+            self.gl.Enable(gl::BLEND);
+            self.gl.BlendColor(0.0, 0.0, 0.0, 1.0 / (i + 1) as f32);
+            self.gl
+                .BlendFunc(gl::CONSTANT_ALPHA, gl::ONE_MINUS_CONSTANT_ALPHA);
+        }
+        self.draw_frame(scene, reference, corners);
+        self.gl.Disable(gl::BLEND);
+    }
+
+    /// Stretches the bottom-left `size` pixels of `framebuffer` over the
+    /// viewport of the bound framebuffer, which is `viewport` pixels and
+    /// starts at the bottom left. Unlike blitting this works into the
+    /// multisampled window of a GLES context.
+    unsafe fn present(&self, framebuffer: &Framebuffer, size: (i32, i32), viewport: (i32, i32)) {
+        let filter = if size == viewport
+            || (framebuffer.format == gl::RGBA32F && !self.formats.filterable_float32)
+        {
+            gl::NEAREST
+        } else {
+            gl::LINEAR
+        };
+        self.gl.UseProgram(self.present_program);
+        self.gl.ActiveTexture(gl::TEXTURE0 + ACCUMULATION_UNIT);
+        self.gl.BindTexture(gl::TEXTURE_2D, framebuffer.texture);
+        self.gl
+            .TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, filter as i32);
+        self.gl
+            .TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, filter as i32);
+        self.gl.Uniform2f(
+            self.gl
+                .GetUniformLocation(self.present_program, c"texel_scale".as_ptr()),
+            size.0 as f32 / (framebuffer.width * viewport.0) as f32,
+            size.1 as f32 / (framebuffer.height * viewport.1) as f32,
+        );
+        self.gl.BindVertexArray(self.vao);
+        self.gl.DrawArrays(gl::TRIANGLE_STRIP, 0, 4);
+        self.gl.BindTexture(gl::TEXTURE_2D, 0);
+    }
+
+    /// Draws `scene` with the given corner rays over the whole viewport, with
+    /// `reference` its orbit for deep zoom.
+    fn draw_frame(
+        &self,
+        scene: &Scene,
+        reference: Option<&ReferenceOrbit>,
+        corners: [glm::Vec3; 4],
+    ) {
+        let uniform_data = UniformData::new(scene, reference);
+
+        unsafe {
+            self.gl.UseProgram(self.program);
+
             self.gl.BindBuffer(gl::ARRAY_BUFFER, self.ray_bo);
             self.gl.BufferSubData(
                 gl::ARRAY_BUFFER,
@@ -192,35 +703,420 @@ impl Renderer {
                 std::mem::size_of::<[glm::Vec3; 4]>() as isize,
                 corners.as_ptr() as *const _,
             );
-            self.gl.BindBuffer(gl::UNIFORM_BUFFER, self.uniform_bo);
-            self.gl.BufferSubData(
-                gl::UNIFORM_BUFFER,
-                0,
-                std::mem::size_of::<UniformData>() as isize,
-                uniform_data.as_ptr() as *const _,
-            );
-            self.gl.BindBuffer(gl::UNIFORM_BUFFER, 0);
+            if self.version.uses_uniform_block() {
+                self.gl.BindBuffer(gl::UNIFORM_BUFFER, self.uniform_bo);
+                self.gl.BufferSubData(
+                    gl::UNIFORM_BUFFER,
+                    0,
+                    std::mem::size_of::<UniformData>() as isize,
+                    [uniform_data].as_ptr() as *const _,
+                );
+                self.gl.BindBuffer(gl::UNIFORM_BUFFER, 0);
+            } else {
+                self.set_uniforms(&uniform_data);
+            }
+            self.upload_palette(&scene.colouring);
+            self.upload_environment(&scene.background);
+            if let Some(reference) = reference {
+                self.upload_reference_orbit(reference, &scene.fractal);
+            }
         }
 
         unsafe {
-            self.gl.UseProgram(self.program);
-
             self.gl.BindVertexArray(self.vao);
             self.gl.BindBuffer(gl::ARRAY_BUFFER, self.vbo);
             // self.gl.BindBuffer(gl::ARRAY_BUFFER, self.ray_bo);
             // self.gl.BindBufferBase(gl::UNIFORM_BUFFER, 0, self.uniform_bo);
 
-            self.gl.ClearColor(red, green, blue, alpha);
-            self.gl.Clear(gl::COLOR_BUFFER_BIT);
             self.gl.DrawArrays(gl::TRIANGLE_STRIP, 0, 4);
         }
     }
 
+    unsafe fn upload_palette(&self, colouring: &Colouring) {
+        let wrap = if colouring.repeat {
+            gl::REPEAT
+        } else {
+            gl::CLAMP_TO_EDGE
+        };
+        self.gl.ActiveTexture(gl::TEXTURE0 + PALETTE_UNIT);
+        self.gl.BindTexture(gl::TEXTURE_2D, self.palette_texture);
+        self.gl
+            .TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, wrap as i32);
+
+        let mut uploaded = self.uploaded_palette.borrow_mut();
+        if uploaded.as_ref() == Some(&colouring.palette) {
+            return;
+        }
+        self.gl.TexSubImage2D(
+            gl::TEXTURE_2D,
+            0,
+            0,
+            0,
+            PALETTE_SIZE as i32,
+            1,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            colouring.palette.bake().as_ptr() as *const _,
+        );
+        *uploaded = Some(colouring.palette.clone());
+    }
+
+    /// Fills the reference orbit texture with full floats, one texel per point,
+    /// unless it already holds the orbit of `fractal` from the same focus.
+    unsafe fn upload_reference_orbit(&self, reference: &ReferenceOrbit, fractal: &Fractal) {
+        self.gl.ActiveTexture(gl::TEXTURE0 + REFERENCE_UNIT);
+        self.gl.BindTexture(gl::TEXTURE_2D, self.reference_texture);
+
+        let mut uploaded = self.uploaded_reference.borrow_mut();
+        let key = (reference.focus, fractal.clone());
+        if uploaded.as_ref() == Some(&key) {
+            return;
+        }
+        self.gl.TexImage2D(
+            gl::TEXTURE_2D,
+            0,
+            gl::RGB32F as i32,
+            reference.points.len() as i32,
+            1,
+            0,
+            gl::RGB,
+            gl::FLOAT,
+            reference.points.as_ptr() as *const _,
+        );
+        *uploaded = Some(key);
+    }
+
+    unsafe fn upload_environment(&self, background: &Background) {
+        let Some(environment) = background.environment() else {
+            return;
+        };
+        let mut uploaded = self.uploaded_environment.borrow_mut();
+        if uploaded
+            .as_ref()
+            .is_some_and(|uploaded| Arc::ptr_eq(uploaded, environment))
+        {
+            return;
+        }
+        for (unit, texture, image) in [
+            (
+                ENVIRONMENT_UNIT,
+                self.environment_texture,
+                &environment.image,
+            ),
+            (
+                IRRADIANCE_UNIT,
+                self.irradiance_texture,
+                &environment.irradiance,
+            ),
+        ] {
+            self.gl.ActiveTexture(gl::TEXTURE0 + unit);
+            self.gl.BindTexture(gl::TEXTURE_2D, texture);
+            upload_float_image(&self.gl, image);
+        }
+        *uploaded = Some(environment.clone());
+    }
+
+    /// Sets the uniforms of the current program one by one. Names the
+    /// compiler optimised out have location -1, which GL ignores.
+    unsafe fn set_uniforms(&self, uniform_data: &UniformData) {
+        for (name, value) in uniform_data.values() {
+            let location = self.gl.GetUniformLocation(self.program, name.as_ptr());
+            match value {
+                UniformValue::Float(value) => self.gl.Uniform1f(location, value),
+                UniformValue::Int(value) => self.gl.Uniform1i(location, value),
+                UniformValue::Vec3(value) => self.gl.Uniform3f(location, value.x, value.y, value.z),
+            }
+        }
+    }
+
     pub fn resize(&self, width: i32, height: i32) {
         unsafe {
             self.gl.Viewport(0, 0, width, height);
         }
     }
+
+    /// Renders an image of any size by drawing it one tile of at most
+    /// `tile_size` pixels square at a time, each with its own slice of the
+    /// camera's frustum, and stitching the tiles together. Every tile is read
+    /// back before the next is drawn, so no single submission runs long
+    /// enough to trip the GPU watchdog. `progress` is called with the number
+    /// of finished and total tiles after each one.
+    ///
+    /// Each pixel is the average of `samples` jittered samples, or a single
+    /// sample on contexts that can not average them.
+    pub fn render_tiled(
+        &self,
+        scene: &Scene,
+        width: u32,
+        height: u32,
+        tile_size: u32,
+        samples: u32,
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<Image, Box<dyn Error>> {
+        let mut scene = scene.clone();
+        scene.camera.set_aspect(width as f32, height as f32);
+        let corners = scene.camera.get_corners();
+        let reference = ReferenceOrbit::new(&scene);
+        let tiles = Tile::split(width, height, tile_size);
+        let framebuffer_width = tile_size.min(width) as i32;
+        let framebuffer_height = tile_size.min(height) as i32;
+
+        unsafe {
+            let mut viewport = [0; 4];
+            self.gl.GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+
+            let accumulation = if samples > 1 {
+                Framebuffer::widest(
+                    &self.gl,
+                    &self.formats,
+                    framebuffer_width,
+                    framebuffer_height,
+                )
+            } else {
+                None
+            };
+            let framebuffer =
+                Framebuffer::new(&self.gl, framebuffer_width, framebuffer_height, gl::RGBA8);
+            let status = self.gl.CheckFramebufferStatus(gl::FRAMEBUFFER);
+            let image = if status == gl::FRAMEBUFFER_COMPLETE {
+                let mut image = Image::new(width, height);
+                for (i, tile) in tiles.iter().enumerate() {
+                    let (tile_width, tile_height) = (tile.width as i32, tile.height as i32);
+                    self.gl.Viewport(0, 0, tile_width, tile_height);
+                    match &accumulation {
+                        Some(accumulation) => {
+                            accumulation.bind(&self.gl);
+                            for sample in 0..samples {
+                                let jittered = scene.camera.get_jittered_corners(
+                                    sample_offset(sample),
+                                    width as f32,
+                                    height as f32,
+                                );
+                                let corners = tile.corners(&jittered, width, height);
+                                self.draw_sample(&scene, reference.as_ref(), corners, sample);
+                            }
+                            framebuffer.bind(&self.gl);
+                            let tile_size = (tile_width, tile_height);
+                            self.present(accumulation, tile_size, tile_size);
+                        }
+                        None => self.draw_frame(
+                            &scene,
+                            reference.as_ref(),
+                            tile.corners(&corners, width, height),
+                        ),
+                    }
+                    image.paste(&self.read_pixels(tile_width, tile_height), tile.x, tile.y);
+                    progress(i + 1, tiles.len());
+                }
+                Ok(image)
+            } else {
+                Err(format!(
+                    "framebuffer of size {framebuffer_width}x{framebuffer_height} is incomplete \
+                     ({status:#x})"
+                )
+                .into())
+            };
+            framebuffer.delete(&self.gl);
+            if let Some(accumulation) = accumulation {
+                accumulation.delete(&self.gl);
+            }
+
+            self.gl
+                .Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+            image
+        }
+    }
+
+    /// Queries what the context supports and how large it can render.
+    pub fn capabilities(&self) -> Capabilities {
+        let string = |name| {
+            get_gl_string(&self.gl, name).map_or_else(
+                || "unknown".to_owned(),
+                |s| s.to_string_lossy().into_owned(),
+            )
+        };
+        let mut max_texture_size = 0;
+        let mut max_viewport_dims = [0; 2];
+        let accumulation_format = unsafe {
+            self.gl
+                .GetIntegerv(gl::MAX_TEXTURE_SIZE, &mut max_texture_size);
+            self.gl
+                .GetIntegerv(gl::MAX_VIEWPORT_DIMS, max_viewport_dims.as_mut_ptr());
+            Framebuffer::widest(&self.gl, &self.formats, 1, 1).map(|framebuffer| {
+                let format = framebuffer.format;
+                framebuffer.delete(&self.gl);
+                format
+            })
+        };
+        Capabilities {
+            vendor: string(gl::VENDOR),
+            renderer: string(gl::RENDERER),
+            version: string(gl::VERSION),
+            shading_language_version: string(gl::SHADING_LANGUAGE_VERSION),
+            glsl: self.version,
+            max_texture_size,
+            max_viewport_dims,
+            accumulation_format,
+        }
+    }
+
+    /// Reads the bottom-left `width` x `height` pixels of the bound framebuffer.
+    pub fn read_pixels(&self, width: i32, height: i32) -> Image {
+        let mut image = Image::new(width as u32, height as u32);
+        unsafe {
+            self.gl.PixelStorei(gl::PACK_ALIGNMENT, 1);
+            self.gl.ReadPixels(
+                0,
+                0,
+                width,
+                height,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                image.pixels.as_mut_ptr() as *mut _,
+            );
+        }
+        // GL rows go from bottom to top.
+        image.flip_vertically();
+        image
+    }
+}
+
+impl FormatSupport {
+    /// Desktop GL blends into every float format it can render to. GLES
+    /// needs `EXT_color_buffer_half_float` or `EXT_color_buffer_float` to
+    /// blend 16-bit floats, `EXT_float_blend` for 32-bit floats and
+    /// `OES_texture_float_linear` to filter them.
+    unsafe fn query(gl: &gl::Gl, version: GlslVersion) -> Self {
+        if version != GlslVersion::Es300 {
+            return Self {
+                blendable: ACCUMULATION_FORMATS.to_vec(),
+                filterable_float32: true,
+            };
+        }
+        let extensions = extensions(gl);
+        let has = |name: &str| extensions.iter().any(|extension| extension == name);
+        let blendable = ACCUMULATION_FORMATS
+            .into_iter()
+            .filter(|&format| match format {
+                gl::RGBA32F => has("GL_EXT_float_blend"),
+                gl::RGBA16F => {
+                    has("GL_EXT_color_buffer_half_float") || has("GL_EXT_color_buffer_float")
+                }
+                _ => true,
+            })
+            .collect();
+        Self {
+            blendable,
+            filterable_float32: has("GL_OES_texture_float_linear"),
+        }
+    }
+}
+
+impl Accumulation {
+    /// Creates the framebuffer in the widest format the context can render
+    /// and blend in. Returns `None` if the context can not average samples.
+    unsafe fn new(gl: &gl::Gl, formats: &FormatSupport, width: i32, height: i32) -> Option<Self> {
+        let framebuffer = Framebuffer::widest(gl, formats, width, height)?;
+        gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
+        Some(Self {
+            framebuffer,
+            width,
+            height,
+            samples: 0,
+            inputs: None,
+        })
+    }
+}
+
+/// A framebuffer object with a texture of the given internal format as its
+/// colour attachment. It is bound on creation and the default framebuffer is
+/// bound again on deletion.
+struct Framebuffer {
+    fbo: gl::types::GLuint,
+    texture: gl::types::GLuint,
+    format: gl::types::GLenum,
+    width: i32,
+    height: i32,
+}
+
+impl Framebuffer {
+    unsafe fn new(gl: &gl::Gl, width: i32, height: i32, format: gl::types::GLenum) -> Self {
+        let kind = if format == gl::RGBA8 {
+            gl::UNSIGNED_BYTE
+        } else {
+            gl::FLOAT
+        };
+        let mut texture = 0;
+        gl.GenTextures(1, &mut texture);
+        gl.BindTexture(gl::TEXTURE_2D, texture);
+        gl.TexImage2D(
+            gl::TEXTURE_2D,
+            0,
+            format as i32,
+            width,
+            height,
+            0,
+            gl::RGBA,
+            kind,
+            null(),
+        );
+        gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+        gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+        gl.BindTexture(gl::TEXTURE_2D, 0);
+
+        let mut fbo = 0;
+        gl.GenFramebuffers(1, &mut fbo);
+        gl.BindFramebuffer(gl::FRAMEBUFFER, fbo);
+        gl.FramebufferTexture2D(
+            gl::FRAMEBUFFER,
+            gl::COLOR_ATTACHMENT0,
+            gl::TEXTURE_2D,
+            texture,
+            0,
+        );
+
+        Self {
+            fbo,
+            texture,
+            format,
+            width,
+            height,
+        }
+    }
+
+    /// Creates a framebuffer for averaging samples in, in the widest of the
+    /// `formats` the context can blend in that it can also render to. It is
+    /// left bound. Returns `None` if the context has no framebuffer objects or
+    /// can not blend samples.
+    unsafe fn widest(
+        gl: &gl::Gl,
+        formats: &FormatSupport,
+        width: i32,
+        height: i32,
+    ) -> Option<Self> {
+        if !gl.GenFramebuffers.is_loaded() || !gl.BlendColor.is_loaded() {
+            return None;
+        }
+        formats.blendable.iter().find_map(|&format| {
+            let framebuffer = Framebuffer::new(gl, width, height, format);
+            if gl.CheckFramebufferStatus(gl::FRAMEBUFFER) == gl::FRAMEBUFFER_COMPLETE {
+                Some(framebuffer)
+            } else {
+                framebuffer.delete(gl);
+                None
+            }
+        })
+    }
+
+    unsafe fn bind(&self, gl: &gl::Gl) {
+        gl.BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+    }
+
+    unsafe fn delete(self, gl: &gl::Gl) {
+        gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
+        gl.DeleteFramebuffers(1, &self.fbo);
+        gl.DeleteTextures(1, &self.texture);
+    }
 }
 
 impl Deref for Renderer {
@@ -234,7 +1130,15 @@ impl Deref for Renderer {
 impl Drop for Renderer {
     fn drop(&mut self) {
         unsafe {
+            if let Some(accumulation) = self.accumulation.take() {
+                accumulation.framebuffer.delete(&self.gl);
+            }
             self.gl.DeleteProgram(self.program);
+            self.gl.DeleteProgram(self.present_program);
+            self.gl.DeleteTextures(1, &self.palette_texture);
+            self.gl.DeleteTextures(1, &self.environment_texture);
+            self.gl.DeleteTextures(1, &self.irradiance_texture);
+            self.gl.DeleteTextures(1, &self.reference_texture);
             self.gl.DeleteBuffers(1, &self.uniform_bo);
             self.gl.DeleteBuffers(1, &self.ray_bo);
             self.gl.DeleteBuffers(1, &self.vbo);
@@ -243,11 +1147,187 @@ impl Drop for Renderer {
     }
 }
 
-unsafe fn create_shader(
+/// Attribute locations and the uniform block binding, fixed before linking so
+/// that they stay the same when the program is reloaded.
+const POSITION_ATTRIB: gl::types::GLuint = 0;
+const RAY_ATTRIB: gl::types::GLuint = 1;
+const UNIFORM_BINDING: gl::types::GLuint = 0;
+const PALETTE_UNIT: gl::types::GLuint = 0;
+const ENVIRONMENT_UNIT: gl::types::GLuint = 1;
+const IRRADIANCE_UNIT: gl::types::GLuint = 2;
+const REFERENCE_UNIT: gl::types::GLuint = 3;
+const ACCUMULATION_UNIT: gl::types::GLuint = 4;
+/// Formats tried for the framebuffers samples are averaged in, widest first.
+const ACCUMULATION_FORMATS: [gl::types::GLenum; 3] = [gl::RGBA32F, gl::RGBA16F, gl::RGBA8];
+
+unsafe fn create_program(
+    gl: &gl::Gl,
+    version: GlslVersion,
+    fragment_source: &ShaderSource,
+) -> Result<gl::types::GLuint, RendererError> {
+    let program = link_program(gl, version, fragment_source)?;
+    if let Err(err) = check_program(gl, version, program) {
+        gl.DeleteProgram(program);
+        return Err(err);
+    }
+
+    if version.uses_uniform_block() {
+        let uniform_block = gl.GetUniformBlockIndex(program, c"uni".as_ptr());
+        gl.UniformBlockBinding(program, uniform_block, UNIFORM_BINDING);
+    }
+    gl.UseProgram(program);
+    for (name, unit) in [
+        (c"palette", PALETTE_UNIT),
+        (c"environment", ENVIRONMENT_UNIT),
+        (c"irradiance", IRRADIANCE_UNIT),
+        (c"reference_orbit", REFERENCE_UNIT),
+    ] {
+        gl.Uniform1i(gl.GetUniformLocation(program, name.as_ptr()), unit as i32);
+    }
+
+    Ok(program)
+}
+
+/// The program `Renderer::present` draws with, the embedded vertex shader
+/// with the present fragment shader.
+unsafe fn create_present_program(
+    gl: &gl::Gl,
+    version: GlslVersion,
+) -> Result<gl::types::GLuint, RendererError> {
+    let program = link_program(gl, version, &ShaderSource::present())?;
+    gl.UseProgram(program);
+    gl.Uniform1i(
+        gl.GetUniformLocation(program, c"accumulation".as_ptr()),
+        ACCUMULATION_UNIT as i32,
+    );
+    Ok(program)
+}
+
+/// Compiles the embedded vertex shader and `fragment_source` and links them,
+/// with the attributes at their fixed locations.
+unsafe fn link_program(
+    gl: &gl::Gl,
+    version: GlslVersion,
+    fragment_source: &ShaderSource,
+) -> Result<gl::types::GLuint, RendererError> {
+    let vertex_shader = compile_source(gl, version, &ShaderSource::embedded(ShaderStage::Vertex))?;
+    let fragment_shader = match compile_source(gl, version, fragment_source) {
+        Ok(shader) => shader,
+        Err(err) => {
+            gl.DeleteShader(vertex_shader);
+            return Err(err);
+        }
+    };
+
+    let program = gl.CreateProgram();
+
+    gl.AttachShader(program, vertex_shader);
+    gl.AttachShader(program, fragment_shader);
+    gl.BindAttribLocation(program, POSITION_ATTRIB, c"position".as_ptr());
+    gl.BindAttribLocation(program, RAY_ATTRIB, c"ray".as_ptr());
+
+    gl.LinkProgram(program);
+
+    gl.DeleteShader(vertex_shader);
+    gl.DeleteShader(fragment_shader);
+
+    let mut success = 1;
+    gl.GetProgramiv(program, gl::LINK_STATUS, &mut success);
+    if success == 0 {
+        let mut len = 0;
+        gl.GetProgramiv(program, gl::INFO_LOG_LENGTH, &mut len);
+        let mut buffer = vec![0u8; len as usize];
+        gl.GetProgramInfoLog(program, len, &mut len, buffer.as_mut_ptr() as *mut _);
+        buffer.truncate(len as usize);
+        gl.DeleteProgram(program);
+        return Err(RendererError::Link(
+            String::from_utf8_lossy(&buffer).into_owned(),
+        ));
+    }
+
+    Ok(program)
+}
+
+/// A linearly filtered texture for an equirectangular map, which wraps around
+/// horizontally. It starts out as a single black texel.
+unsafe fn create_float_texture(gl: &gl::Gl) -> gl::types::GLuint {
+    let mut texture = 0;
+    gl.GenTextures(1, &mut texture);
+    gl.BindTexture(gl::TEXTURE_2D, texture);
+    upload_float_image(
+        gl,
+        &HdrImage {
+            width: 1,
+            height: 1,
+            pixels: vec![glm::vec3(0.0, 0.0, 0.0)],
+        },
+    );
+    gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+    gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+    gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as i32);
+    gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+    gl.BindTexture(gl::TEXTURE_2D, 0);
+    texture
+}
+
+/// Replaces the bound texture with `image` as half floats, the widest format
+/// GLES 3 can filter. Its top row becomes the first row of the texture.
+unsafe fn upload_float_image(gl: &gl::Gl, image: &HdrImage) {
+    gl.TexImage2D(
+        gl::TEXTURE_2D,
+        0,
+        gl::RGB16F as i32,
+        image.width as i32,
+        image.height as i32,
+        0,
+        gl::RGB,
+        gl::FLOAT,
+        image.pixels.as_ptr() as *const _,
+    );
+}
+
+unsafe fn compile_source(
+    gl: &gl::Gl,
+    version: GlslVersion,
+    source: &ShaderSource,
+) -> Result<gl::types::GLuint, RendererError> {
+    let kind = match source.stage() {
+        ShaderStage::Vertex => gl::VERTEX_SHADER,
+        ShaderStage::Fragment => gl::FRAGMENT_SHADER,
+    };
+    create_shader(gl, kind, source.to_cstring(version).as_bytes_with_nul()).map_err(|log| {
+        RendererError::Compile {
+            stage: source.stage(),
+            log: source.annotate_log(&log),
+        }
+    })
+}
+
+/// Checks that the linked `program` has everything `Renderer` binds to it.
+unsafe fn check_program(
+    gl: &gl::Gl,
+    version: GlslVersion,
+    program: gl::types::GLuint,
+) -> Result<(), RendererError> {
+    for (name, c_name) in [("position", c"position"), ("ray", c"ray")] {
+        if gl.GetAttribLocation(program, c_name.as_ptr()) < 0 {
+            return Err(RendererError::MissingAttribute(name));
+        }
+    }
+    if version.uses_uniform_block()
+        && gl.GetUniformBlockIndex(program, c"uni".as_ptr()) == gl::INVALID_INDEX
+    {
+        return Err(RendererError::MissingUniformBlock("uni"));
+    }
+
+    Ok(())
+}
+
+pub(crate) unsafe fn create_shader(
     gl: &gl::Gl,
     shader: gl::types::GLenum,
     source: &[u8],
-) -> gl::types::GLuint {
+) -> Result<gl::types::GLuint, String> {
     let shader = gl.CreateShader(shader);
     gl.ShaderSource(
         shader,
@@ -264,14 +1344,27 @@ unsafe fn create_shader(
         let mut len = 0;
         gl.GetShaderiv(shader, gl::INFO_LOG_LENGTH, &mut len);
 
-        let mut buffer: Vec<u8> = Vec::with_capacity(len as usize);
-        buffer.set_len(len as usize);
-        gl.GetShaderInfoLog(shader, len, null_mut(), buffer.as_mut_ptr() as *mut i8);
+        let mut buffer = vec![0u8; len as usize];
+        gl.GetShaderInfoLog(shader, len, &mut len, buffer.as_mut_ptr() as *mut _);
+        buffer.truncate(len as usize);
+        gl.DeleteShader(shader);
 
-        println!("{}", String::from_utf8_unchecked(buffer));
+        return Err(String::from_utf8_lossy(&buffer).into_owned());
     }
 
-    shader
+    Ok(shader)
+}
+
+/// The names of the extensions a GL 3 or GLES 3 context supports.
+unsafe fn extensions(gl: &gl::Gl) -> Vec<String> {
+    let mut count = 0;
+    gl.GetIntegerv(gl::NUM_EXTENSIONS, &mut count);
+    (0..count.max(0) as u32)
+        .filter_map(|i| {
+            let name = gl.GetStringi(gl::EXTENSIONS, i);
+            (!name.is_null()).then(|| CStr::from_ptr(name.cast()).to_string_lossy().into_owned())
+        })
+        .collect()
 }
 
 fn get_gl_string(gl: &gl::Gl, variant: gl::types::GLenum) -> Option<&'static CStr> {
@@ -288,20 +1381,3 @@ static VERTEX_DATA: [f32; 8] = [
     1.0, -1.0,
     1.0, 1.0
 ];
-
-const VERTEX_SHADER_SOURCE: &[u8] = b"
-#version 100
-precision mediump float;
-
-attribute vec2 position;
-attribute vec3 ray;
-
-varying vec3 ray_direction;
-
-void main() {
-    gl_Position = vec4(position, 0.0, 1.0);
-    ray_direction = ray;
-}
-\0";
-
-const FRAGMENT_SHADER_SOURCE: &CStr = crate::macros::include_cstr!("shader/mandelbulb.glsl");
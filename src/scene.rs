@@ -1,38 +1,380 @@
-use crate::{camera::Camera, light::SunLight, three_d::LocalToGlobal};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
 
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    background::Background, camera::Camera, colouring::Colouring, fractal::Fractal,
+    light::SunLight, three_d::LocalToGlobal, track::CameraState,
+};
+
+#[derive(Clone)]
 pub struct Scene {
     pub camera: Camera,
     pub light: SunLight,
+    pub fractal: Fractal,
+    pub render: RenderSettings,
+    pub colouring: Colouring,
+    pub shading: Shading,
+    pub background: Background,
+    pub atmosphere: Atmosphere,
     pub mouse: Option<glm::Vec2>,
     pub mouse_down: bool,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RenderSettings {
+    /// Size of images rendered without a window.
+    pub width: u32,
+    pub height: u32,
+    /// Images rendered without a window are drawn in tiles of at most this
+    /// many pixels square, so large ones don't trip the GPU watchdog.
+    pub tile_size: u32,
+    /// Number of march steps before a ray counts as a miss.
+    pub max_steps: u32,
+    /// Number of jittered samples per pixel averaged in the window while the
+    /// view stays the same. 1 turns the accumulation off.
+    pub samples: u32,
+    /// Frame rate the window aims for while the view changes, by rendering
+    /// at a lower resolution and scaling it up.
+    pub target_fps: f32,
+    /// Smallest fraction of the window size rendered at while the view
+    /// changes. 1 always renders at full resolution.
+    pub min_resolution_scale: f32,
+    /// Iterates the Mandelbulb and Mandelbox relative to an orbit computed in
+    /// double precision at the camera's focus, for zooming in further than
+    /// floats alone allow. Without it the fractals are evaluated at absolute
+    /// `f32` positions, however precisely the camera is placed. Slower, so
+    /// off by default.
+    pub deep_zoom: bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            width: 800,
+            height: 600,
+            tile_size: 1024,
+            max_steps: 128,
+            samples: 64,
+            target_fps: 30.0,
+            min_resolution_scale: 0.25,
+            deep_zoom: false,
+        }
+    }
+}
+
+impl RenderSettings {
+    /// Rejects the sizes and counts that are zero, which nothing can be
+    /// rendered with.
+    fn check(&self) -> Result<(), String> {
+        let counts = [
+            ("width", self.width),
+            ("height", self.height),
+            ("tile_size", self.tile_size),
+            ("max_steps", self.max_steps),
+            ("samples", self.samples),
+        ];
+        match counts.iter().find(|(_, count)| *count == 0) {
+            Some((name, _)) => Err(format!("render.{name} should be above 0")),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Which lighting terms are added up for a hit, and how strongly. The light
+/// is the scene's `SunLight`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Shading {
+    /// Light from the background so that faces turned away from the sun are
+    /// not black.
+    pub ambient: bool,
+    pub ambient_strength: f32,
+    /// Lambert diffuse light.
+    pub diffuse: bool,
+    /// Blinn-Phong highlights.
+    pub specular: bool,
+    pub specular_strength: f32,
+    pub shininess: f32,
+    /// Light along the silhouette, where the surface turns away from the
+    /// camera.
+    pub rim: bool,
+    pub rim_strength: f32,
+    pub rim_power: f32,
+    /// Soft shadows from the sun on the diffuse and specular light, with
+    /// penumbras that narrow as the sharpness goes up.
+    pub shadows: bool,
+    pub shadow_sharpness: f32,
+    /// Number of march steps of a shadow ray.
+    pub shadow_steps: u32,
+    /// Darkening of the ambient light in crevices, from distance field
+    /// samples along the normal up to `occlusion_radius` away. The radius is
+    /// in units of the camera's focal distance, so it stays in proportion to
+    /// the surface detail at any zoom.
+    pub occlusion: bool,
+    pub occlusion_strength: f32,
+    pub occlusion_radius: f32,
+    pub occlusion_samples: u32,
+    /// Darkening of the ambient light by the number of march steps a hit
+    /// took, also known as glow occlusion.
+    pub step_occlusion: bool,
+    pub step_occlusion_strength: f32,
+    /// Mirror reflection of the background, not of the fractal itself.
+    pub reflection: bool,
+    pub reflection_strength: f32,
+}
+
+impl Default for Shading {
+    fn default() -> Self {
+        Self {
+            ambient: true,
+            ambient_strength: 0.1,
+            diffuse: true,
+            specular: true,
+            specular_strength: 0.5,
+            shininess: 32.0,
+            rim: false,
+            rim_strength: 0.3,
+            rim_power: 3.0,
+            shadows: true,
+            shadow_sharpness: 16.0,
+            shadow_steps: 64,
+            occlusion: true,
+            occlusion_strength: 1.0,
+            occlusion_radius: 0.1,
+            occlusion_samples: 5,
+            step_occlusion: true,
+            step_occlusion_strength: 0.5,
+            reflection: false,
+            reflection_strength: 0.2,
+        }
+    }
+}
+
+/// Fog and glow along the rays. Lengths are in units of the camera's focal
+/// distance, which shrinks as the camera zooms in, so the fog keeps the same
+/// depth at any zoom.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Atmosphere {
+    /// Exponential fog in the colour of the background. Hits `t` away keep
+    /// `exp(-fog_density * t)` of their own colour.
+    pub fog: bool,
+    pub fog_density: f32,
+    /// How quickly the fog thins out above `fog_height`, zero for fog of the
+    /// same density everywhere.
+    pub fog_falloff: f32,
+    /// World height at which the fog has `fog_density`. It stays put as the
+    /// camera moves, so the camera can climb out of the fog.
+    pub fog_height: f32,
+    /// Light added by the number of march steps, so that rays which pass
+    /// close to the surface glow. It builds up over the first `glow_depth`
+    /// or so of the march, like light scattered by a medium.
+    pub glow: bool,
+    pub glow_strength: f32,
+    #[serde(with = "crate::serde_glm::vec3")]
+    pub glow_color: glm::Vec3,
+    pub glow_depth: f32,
+}
+
+impl Default for Atmosphere {
+    fn default() -> Self {
+        Self {
+            fog: false,
+            fog_density: 0.2,
+            fog_falloff: 0.0,
+            fog_height: 0.0,
+            glow: false,
+            glow_strength: 0.5,
+            glow_color: glm::vec3(1.0, 0.8, 0.6),
+            glow_depth: 1.0,
+        }
+    }
+}
+
+/// The on-disk form of a `Scene`, stored as TOML.
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+struct SceneFile {
+    camera: CameraDescription,
+    light: SunLight,
+    fractal: Fractal,
+    render: RenderSettings,
+    colouring: Colouring,
+    shading: Shading,
+    background: Background,
+    atmosphere: Atmosphere,
+}
+
+impl Default for SceneFile {
+    fn default() -> Self {
+        let light = SunLight {
+            camera_direction: Some(glm::vec3(-1.0, -1.0, -1.0)),
+            ..SunLight::new()
+        };
+        Self {
+            camera: CameraDescription::default(),
+            light,
+            fractal: Fractal::default(),
+            render: RenderSettings::default(),
+            colouring: Colouring::default(),
+            shading: Shading::default(),
+            background: Background::default(),
+            atmosphere: Atmosphere::default(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+struct CameraDescription {
+    #[serde(with = "crate::serde_glm::dvec3")]
+    position: glm::DVec3,
+    #[serde(with = "crate::serde_glm::vec3")]
+    forward: glm::Vec3,
+    /// Towards the top of the screen, or as close to it as it gets at right
+    /// angles to `forward`.
+    #[serde(with = "crate::serde_glm::vec3")]
+    up: glm::Vec3,
+    fov: f32,
+}
+
+impl Default for CameraDescription {
+    fn default() -> Self {
+        let camera = Camera::new();
+        Self {
+            position: glm::dvec3(0.0, 0.0, 2.0),
+            forward: camera.forward(),
+            up: camera.up(),
+            fov: camera.fov,
+        }
+    }
+}
+
 impl Scene {
     pub fn init() -> Self {
-        let mut scene = Self {
-            camera: Camera::new(),
-            light: SunLight::new(),
-            mouse: None,
-            mouse_down: false,
-        };
-        scene
-            .camera
-            .set_position_and_forward(glm::vec3(0.0, 0.0, 2.0), scene.camera.forward);
+        Self::from_file(SceneFile::default())
+    }
 
-        // scene.camera.set_position_and_forward(glm::vec3(0.018368, 0.016674, 0.027951), glm::vec3(0.2, 0.0, 0.0));
-        scene.light.direction = glm::normalize(glm::vec3(-1.0, -1.0, -1.0));
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let file: SceneFile = toml::from_str(&fs::read_to_string(path)?)?;
+        file.render.check()?;
+        let mut scene = Self::from_file(file);
         scene
+            .background
+            .load(path.parent().unwrap_or(Path::new(".")))?;
+        Ok(scene)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let file = SceneFile {
+            camera: CameraDescription {
+                position: self.camera.position,
+                forward: self.camera.forward(),
+                up: self.camera.up(),
+                fov: self.camera.fov,
+            },
+            light: self.light.clone(),
+            fractal: self.fractal.clone(),
+            render: self.render.clone(),
+            colouring: self.colouring.clone(),
+            shading: self.shading.clone(),
+            background: self.background.clone(),
+            atmosphere: self.atmosphere.clone(),
+        };
+        fs::write(path, toml::to_string_pretty(&file)?)?;
+        Ok(())
+    }
+
+    fn from_file(file: SceneFile) -> Self {
+        let mut camera = Camera::new();
+        camera.fov = file.camera.fov;
+        camera.set_state(CameraState::looking_along(
+            file.camera.position,
+            glm::to_dvec3(file.camera.forward),
+            glm::to_dvec3(file.camera.up),
+        ));
+
+        let mut light = file.light;
+        light.direction = glm::normalize(light.direction);
+
+        Self {
+            camera,
+            light,
+            fractal: file.fractal,
+            render: file.render,
+            colouring: file.colouring,
+            shading: file.shading,
+            background: file.background,
+            atmosphere: file.atmosphere,
+            mouse: None,
+            mouse_down: false,
+        }
     }
 
     pub fn should_update(&self) -> bool {
         self.camera.should_update()
     }
+
+    /// Jumps to the end of any camera animation, for rendering without a
+    /// window where there is no clock driving `update_time`.
+    pub fn settle(&mut self) {
+        self.update_time(self.camera.end_time());
+    }
+
     pub fn update_time(&mut self, t: u128) {
         self.camera.update_time(t);
-        self.light.direction = glm::normalize(
-            self.camera
-                .to_global(&glm::vec3(0., 0., 0.), &glm::vec3(-1., -1., -1.))
-                .1,
-        );
+        if let Some(camera_direction) = self.light.camera_direction {
+            self.light.direction = glm::normalize(
+                self.camera
+                    .to_global(&glm::vec3(0., 0., 0.), &camera_direction)
+                    .1,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Loads a scene file with the given contents.
+    fn load(name: &str, contents: &str) -> Result<Scene, Box<dyn Error>> {
+        let path = std::env::temp_dir().join(format!("{}-{name}.toml", std::process::id()));
+        fs::write(&path, contents).unwrap();
+        let scene = Scene::load(&path);
+        fs::remove_file(&path).unwrap();
+        scene
+    }
+
+    #[test]
+    fn saved_scene_finds_its_environment() {
+        let dir = std::env::temp_dir().join(format!("{}-environment", std::process::id()));
+        let saved = dir.join("saved");
+        fs::create_dir_all(&saved).unwrap();
+        let hdr =
+            b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 2\n\x80\x80\x80\x81\x80\x80\x80\x81";
+        fs::write(dir.join("sky.hdr"), hdr).unwrap();
+        let scene = "[background]\ntype = \"environment\"\npath = \"sky.hdr\"\nexposure = 1.0\n";
+        fs::write(dir.join("scene.toml"), scene).unwrap();
+
+        let scene = Scene::load(dir.join("scene.toml")).unwrap();
+        scene.save(saved.join("scene.toml")).unwrap();
+        let reloaded = Scene::load(saved.join("scene.toml"));
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(reloaded.unwrap().background.environment().is_some());
+    }
+
+    #[test]
+    fn rejects_zero_render_settings() {
+        assert!(load("default", "").is_ok());
+        for name in ["width", "height", "tile_size", "max_steps", "samples"] {
+            let scene = load(name, &format!("[render]\n{name} = 0\n"));
+            assert!(scene.is_err(), "{name}");
+        }
     }
 }
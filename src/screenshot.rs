@@ -0,0 +1,20 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use crate::image::Image;
+use crate::scene::Scene;
+
+/// Saves `image` as `<name>.png` in `dir` together with a `<name>.toml`
+/// sidecar scene file, which can be loaded to render the same view again.
+/// Returns the path of the image.
+pub fn save(
+    dir: &Path,
+    name: &str,
+    image: &Image,
+    scene: &Scene,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let image_path = dir.join(format!("{name}.png"));
+    image.save_png(&image_path)?;
+    scene.save(image_path.with_extension("toml"))?;
+    Ok(image_path)
+}
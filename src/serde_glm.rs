@@ -0,0 +1,46 @@
+//! Helpers for `#[serde(with = "...")]` on `glm` vectors, which are stored as
+//! plain arrays in scene files.
+
+pub mod vec3 {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(v: &glm::Vec3, serializer: S) -> Result<S::Ok, S::Error> {
+        [v.x, v.y, v.z].serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<glm::Vec3, D::Error> {
+        let [x, y, z] = <[f32; 3]>::deserialize(deserializer)?;
+        Ok(glm::vec3(x, y, z))
+    }
+}
+
+pub mod dvec3 {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(v: &glm::DVec3, serializer: S) -> Result<S::Ok, S::Error> {
+        [v.x, v.y, v.z].serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<glm::DVec3, D::Error> {
+        let [x, y, z] = <[f64; 3]>::deserialize(deserializer)?;
+        Ok(glm::dvec3(x, y, z))
+    }
+}
+
+pub mod option_vec3 {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        v: &Option<glm::Vec3>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        v.map(|v| [v.x, v.y, v.z]).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<glm::Vec3>, D::Error> {
+        let v = Option::<[f32; 3]>::deserialize(deserializer)?;
+        Ok(v.map(|[x, y, z]| glm::vec3(x, y, z)))
+    }
+}
@@ -0,0 +1,66 @@
+uniform sampler2D environment;
+uniform sampler2D irradiance;
+
+#define PI 3.14159265
+
+// Cosines of the angles from the sun's centre where its disc starts to fade
+// and where it ends.
+#define SUN_INNER 0.9996
+#define SUN_OUTER 0.9992
+
+// Texture coordinates of the unit direction `dir` in an equirectangular map
+// whose top row is straight up.
+vec2 equirectangular(vec3 dir) {
+    return vec2(atan(dir.z, dir.x) / (2.0 * PI) + 0.5, acos(clamp(dir.y, -1.0, 1.0)) / PI);
+}
+
+// The solid, gradient or sky background along the unit direction `dir`,
+// without the sun.
+vec3 sky_gradient(vec3 dir) {
+    if (background_type == BACKGROUND_GRADIENT) {
+        return mix(background_bottom, background_top, dir.y * 0.5 + 0.5);
+    }
+    if (background_type == BACKGROUND_SKY) {
+        if (dir.y >= 0.0) {
+            return mix(background_horizon, background_top, sqrt(dir.y));
+        }
+        return mix(background_horizon, background_bottom, sqrt(-dir.y));
+    }
+    return background_top;
+}
+
+// What a ray along the unit direction `dir` sees when it misses.
+vec3 background(vec3 dir) {
+    if (background_type == BACKGROUND_ENVIRONMENT) {
+        return exposure * texture(environment, equirectangular(dir)).rgb;
+    }
+    vec3 color = sky_gradient(dir);
+    if (background_type == BACKGROUND_SKY) {
+        float s = max(dot(dir, -light_dir), 0.0);
+        color += light_color * (0.25 * pow(s, 32.0) + smoothstep(SUN_OUTER, SUN_INNER, s));
+    }
+    return color;
+}
+
+// The colour distant hits fade into along the unit direction `dir`. It is the
+// background without the sun, or a blurred environment.
+vec3 fog_colour(vec3 dir) {
+    if (background_type == BACKGROUND_ENVIRONMENT) {
+        return exposure * texture(irradiance, equirectangular(dir)).rgb;
+    }
+    return sky_gradient(dir);
+}
+
+// The background light reaching a surface with unit normal `n`, averaged over
+// the hemisphere around it. Environments have it precomputed in a map, a
+// gradient averages to a third of the way from the middle to the end `n`
+// points at, and the sky just takes the colour along `n`.
+vec3 ambient_light(vec3 n) {
+    if (background_type == BACKGROUND_ENVIRONMENT) {
+        return exposure * texture(irradiance, equirectangular(n)).rgb;
+    }
+    if (background_type == BACKGROUND_GRADIENT) {
+        return mix(background_bottom, background_top, 0.5 + n.y / 3.0);
+    }
+    return sky_gradient(n);
+}
@@ -0,0 +1,68 @@
+UNIFORM_BLOCK_BEGIN(uni)
+    UNIFORM(vec3, origin)
+    UNIFORM(vec3, origin_low)
+    UNIFORM(vec3, light_dir)
+    UNIFORM(vec3, light_color)
+    UNIFORM(float, stop_distance)
+    UNIFORM(vec3, surface_color)
+    UNIFORM(float, power)
+    UNIFORM(float, phase)
+    UNIFORM(int, formula)
+    UNIFORM(int, bulb_iterations)
+    UNIFORM(float, box_scale)
+    UNIFORM(float, min_rad2)
+    UNIFORM(int, box_iterations)
+    UNIFORM(int, max_steps)
+    UNIFORM(float, diffuse)
+    UNIFORM(float, ambient)
+    UNIFORM(float, specular)
+    UNIFORM(float, shininess)
+    UNIFORM(float, rim)
+    UNIFORM(float, rim_power)
+    UNIFORM(float, shadow_sharpness)
+    UNIFORM(int, shadow_steps)
+    UNIFORM(float, occlusion_strength)
+    UNIFORM(float, occlusion_radius)
+    UNIFORM(int, occlusion_samples)
+    UNIFORM(float, step_occlusion_strength)
+    UNIFORM(int, colour_mode)
+    UNIFORM(int, trap_shape)
+    UNIFORM(float, palette_scale)
+    UNIFORM(float, palette_offset)
+    UNIFORM(vec3, trap_vector)
+    UNIFORM(float, trap_scalar)
+    UNIFORM(vec3, background_top)
+    UNIFORM(int, background_type)
+    UNIFORM(vec3, background_horizon)
+    UNIFORM(float, exposure)
+    UNIFORM(vec3, background_bottom)
+    UNIFORM(float, reflection)
+    UNIFORM(vec3, glow)
+    UNIFORM(float, fog_density)
+    UNIFORM(float, fog_falloff)
+    UNIFORM(float, fog_height)
+    UNIFORM(int, deep_zoom)
+    UNIFORM(int, reference_length)
+    UNIFORM(vec3, reference_offset)
+    UNIFORM(float, glow_depth)
+UNIFORM_BLOCK_END
+
+// Values of `Formula` in fractal.rs.
+#define FORMULA_MANDELBULB 0
+#define FORMULA_MANDELBOX 1
+#define FORMULA_ROUND_BOX 2
+#define FORMULA_SPHERE 3
+
+// Values of `ColourMode` and `TrapShape` in colouring.rs.
+#define COLOUR_TRAP 0
+#define COLOUR_ITERATIONS 1
+#define TRAP_POINT 0
+#define TRAP_PLANE 1
+#define TRAP_AXIS 2
+#define TRAP_SPHERE 3
+
+// Values of `Background` in background.rs.
+#define BACKGROUND_SOLID 0
+#define BACKGROUND_GRADIENT 1
+#define BACKGROUND_SKY 2
+#define BACKGROUND_ENVIRONMENT 3
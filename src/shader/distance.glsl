@@ -0,0 +1,124 @@
+float sphere(vec3 p, vec3 c, float r) {
+    return length(c - p) - r;
+}
+
+float sdRoundBox( vec3 p, vec3 b, float r ) {
+  vec3 q = abs(p) - b + r;
+  return length(max(q,0.0)) + min(max(q.x,max(q.y,q.z)),0.0) - r;
+}
+
+float trap_distance(vec3 z) {
+    if (trap_shape == TRAP_PLANE) {
+        return abs(dot(z, trap_vector) - trap_scalar);
+    }
+    if (trap_shape == TRAP_AXIS) {
+        return length(z - trap_vector * dot(z, trap_vector));
+    }
+    if (trap_shape == TRAP_SPHERE) {
+        return abs(length(z - trap_vector) - trap_scalar);
+    }
+    return length(z - trap_vector);
+}
+
+// The value the palette is looked up with, from the closest approach of the
+// orbit to the trap or from the iteration it escaped at.
+float colour_value(float closest, float escape, int iterations) {
+    if (colour_mode == COLOUR_ITERATIONS) {
+        return escape / float(iterations);
+    }
+    return closest;
+}
+
+// `z` raised to `power` in spherical coordinates, where `r` is its length.
+vec3 bulb_power(vec3 z, float r, float power, float phase) {
+    float theta = atan(z.y / z.x) * power;
+    float phi = (asin(z.z / r) + phase) * power;
+    return pow(r, power) * vec3(cos(theta) * cos(phi), sin(theta) * cos(phi), sin(phi));
+}
+
+float mandel(vec3 p, float power, float phase, out float trap) {
+    vec3 z = p;
+    vec3 dz = vec3(0.0);
+    float r;
+    float dr = 1.0;
+    float t0 = 1.0;
+    float closest = 1e10;
+    float escape = float(bulb_iterations);
+    bool escaped = false;
+    for (int i = 0; i < bulb_iterations; ++i) {
+        r = length(z);
+        if(r > 2.0) {
+            if (!escaped) {
+                // Smooth iteration count for a bailout radius of 2.
+                escape = float(i) + 1.0 - log(log(r) / log(2.0)) / log(power);
+                escaped = true;
+            }
+            continue;
+        }
+        closest = min(closest, trap_distance(z));
+        dr = pow(r, power - 1.0) * dr * power + 1.0;
+        z = bulb_power(z, r, power, phase) + p;
+        r = pow(r, power);
+        t0 = min(t0, r);
+    }
+    trap = colour_value(closest, escape, bulb_iterations);
+    return 0.25 * log(r) * r / dr;
+}
+
+// float mandelbox(p) {
+//     vec3 z = p;
+
+//     for (int i = 0; i < 8; ++i) {
+//         z = max(min(2 - z, z) -2 - z)
+
+//         if (dot(z, z) < 0.25) {
+//             z += 4;
+//         } else if (dot(z, z) < 1) {
+//             z /= dot(z, z);
+//         }
+        
+//         z = z + c;
+//     }
+
+//     return 
+// }
+
+float mandelbox(vec3 pos, out float trap) {
+	vec4 scale = vec4(box_scale, box_scale, box_scale, abs(box_scale)) / min_rad2;
+	float absScalem1 = abs(box_scale - 1.0);
+	float AbsScaleRaisedTo1mIters = pow(abs(box_scale), float(1 - box_iterations));
+
+	vec4 p = vec4(pos,1), p0 = p;  // p.w is the distance estimate
+	float closest = 1e10;
+	float escape = float(box_iterations);
+	
+	for (int i=0; i < box_iterations; i++) {
+		p.xyz = clamp(p.xyz, -1.0, 1.0) * 2.0 - p.xyz;  // min;max;mad
+		float r2 = dot(p.xyz, p.xyz);
+		p *= clamp(max(min_rad2/r2, min_rad2), 0.0, 1.0);  // dp3,div,max.sat,mul
+		p = p*scale + p0;
+		closest = min(closest, trap_distance(p.xyz));
+             if ( r2>1000.0) {
+			// The orbit grows by about `box_scale` per iteration once it escapes.
+			escape = float(i) + 1.0 - log(r2 / 1000.0) / max(log(box_scale * box_scale), 1e-3);
+			break;
+		}
+	}
+	trap = colour_value(closest, escape, box_iterations);
+	return ((length(p.xyz) - absScalem1) / p.w - AbsScaleRaisedTo1mIters);
+}
+
+float my_mandel(vec3 p, out float trap) {
+    trap = 1.0;
+
+    if (formula == FORMULA_MANDELBOX) {
+        return mandelbox(p, trap);
+    }
+    if (formula == FORMULA_ROUND_BOX) {
+        return sdRoundBox(p, vec3(1.0, 0.8, 0.6), 0.1);
+    }
+    if (formula == FORMULA_SPHERE) {
+        return sphere(p, vec3(0.0, 0.0, 0.0), 1.0);
+    }
+    return min(mandel(p, power, phase, trap), length(p));
+}
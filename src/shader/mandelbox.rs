@@ -0,0 +1,39 @@
+use glm::{abs, clamp, clamp_s, dot, length, log, max, min, pow, Vec3};
+
+use crate::colouring::OrbitTrap;
+
+/// Port of `mandelbox` in `distance.glsl`. Returns the distance estimate and
+/// the palette value.
+pub fn mandelbox(
+    pos: &Vec3,
+    scale: f32,
+    min_rad2: f32,
+    iterations: u32,
+    trap: &OrbitTrap,
+) -> (f32, f32) {
+    let abs_scale_m1 = abs(scale - 1.0);
+    let abs_scale_raised_to_1m_iters = pow(abs(scale), 1.0 - iterations as f32);
+
+    // `p.w` in the shader, the running derivative for the distance estimate.
+    let mut p = *pos;
+    let mut w: f32 = 1.0;
+    let mut closest: f32 = 1e10;
+    let mut escape = iterations as f32;
+
+    for i in 0..iterations {
+        p = clamp_s(p, -1.0, 1.0) * 2.0 - p;
+        let r2 = dot(p, p);
+        let k = clamp(max(min_rad2 / r2, min_rad2), 0.0, 1.0);
+        p = p * k * (scale / min_rad2) + *pos;
+        w = w * k * (abs(scale) / min_rad2) + 1.0;
+        closest = min(closest, trap.shape.distance(p));
+        if r2 > 1000.0 {
+            escape = i as f32 + 1.0 - log(r2 / 1000.0) / max(log(scale * scale), 1e-3);
+            break;
+        }
+    }
+    (
+        (length(p) - abs_scale_m1) / w - abs_scale_raised_to_1m_iters,
+        trap.value(closest, escape, iterations),
+    )
+}
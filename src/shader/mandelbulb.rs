@@ -1,24 +1,45 @@
-use glm::{asin, atan, cos, length, log, max, pow, sin, vec3};
+use glm::{asin, atan, cos, length, log, min, pow, sin, vec3};
 
-pub fn mandelbulb(p: &glm::Vec3, power: f32, phase: f32) -> f32 {
+use crate::colouring::OrbitTrap;
+
+/// Port of `bulb_power` in `distance.glsl`.
+pub fn bulb_power(z: glm::Vec3, r: f32, power: f32, phase: f32) -> glm::Vec3 {
+    let theta = atan(z.y / z.x) * power;
+    let phi = (asin(z.z / r) + phase) * power;
+    vec3(cos(theta) * cos(phi), sin(theta) * cos(phi), sin(phi)) * pow(r, power)
+}
+
+/// Port of `mandel` in `distance.glsl`. Returns the distance estimate and
+/// the palette value.
+pub fn mandel(
+    p: &glm::Vec3,
+    power: f32,
+    phase: f32,
+    iterations: u32,
+    trap: &OrbitTrap,
+) -> (f32, f32) {
     let mut z = *p;
     let mut r: f32 = 0.0;
-    let mut theta: f32;
-    let mut phi: f32;
     let mut dr = 1.0;
-    for _ in 0..32 {
-        // change i < # for iterations.
+    let mut closest: f32 = 1e10;
+    let mut escape = iterations as f32;
+    let mut escaped = false;
+    for i in 0..iterations {
         r = length(z);
         if r > 2.0 {
+            if !escaped {
+                escape = i as f32 + 1.0 - log(log(r) / log(2.0)) / log(power);
+                escaped = true;
+            }
             continue;
         }
-        theta = atan(z.y / z.x);
-        phi = asin(z.z / r) + phase;
+        closest = min(closest, trap.shape.distance(z));
         dr = pow(r, power - 1.0) * dr * power + 1.0;
+        z = bulb_power(z, r, power, phase) + *p;
         r = pow(r, power);
-        theta = theta * power;
-        phi = phi * power;
-        z = vec3(cos(theta) * cos(phi) * r, sin(theta) * cos(phi), sin(phi));
     }
-    return max(0.001, 0.25 * log(r) * r / dr);
+    (
+        0.25 * log(r) * r / dr,
+        trap.value(closest, escape, iterations),
+    )
 }
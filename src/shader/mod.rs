@@ -1 +1,7 @@
+pub mod mandelbox;
 pub mod mandelbulb;
+pub mod perturbation;
+pub mod primitives;
+pub mod shading;
+pub mod source;
+pub mod version;
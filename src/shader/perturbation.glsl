@@ -0,0 +1,182 @@
+uniform sampler2D reference_orbit;
+
+// The point `offset` away from the camera. The low part of the camera
+// position is added to the offset first, so the sum only rounds once, but it
+// still rounds to the floats around the camera.
+vec3 world_position(vec3 offset) {
+    return origin + (origin_low + offset);
+}
+
+// Point `i` of the reference orbit.
+vec3 reference(int i) {
+    return texture(reference_orbit, vec2((float(i) + 0.5) / float(reference_length + 1), 0.5)).xyz;
+}
+
+// `(1 + u)^n - 1` without losing the precision of a small `u`.
+float pow_minus_one(float u, float n) {
+    if (abs(u) > 1e-2) {
+        return pow(1.0 + u, n) - 1.0;
+    }
+    return n * u * (1.0 + (n - 1.0) * u / 2.0 * (1.0 + (n - 2.0) * u / 3.0 * (1.0 + (n - 3.0) * u / 4.0)));
+}
+
+// `bulb_power` of `z + delta` minus that of `z`. It is worked out from the
+// differences of the spherical coordinates, which come from `delta` without
+// subtracting nearly equal numbers, so it keeps the precision of a small
+// `delta`. Only valid while both points are on the same side of x = 0, where
+// `atan(y / x)` jumps.
+vec3 bulb_power_delta(vec3 z, vec3 delta, float power, float phase) {
+    vec3 w = z + delta;
+    float r = length(z);
+    float r_w = length(w);
+    float rho = length(z.xy);
+    float d_r = dot(2.0 * z + delta, delta) / (r_w + r);
+    float d_rho = dot(2.0 * z.xy + delta.xy, delta.xy) / (length(w.xy) + rho);
+
+    // The angle differences have their tangent and sine as cross products.
+    float theta = atan(z.y / z.x) * power;
+    float phi = (asin(z.z / r) + phase) * power;
+    float d_theta = atan((z.x * delta.y - z.y * delta.x) / dot(z.xy, w.xy)) * power;
+    float d_phi = asin(clamp((delta.z * rho - d_rho * z.z) / (r * r_w), -1.0, 1.0)) * power;
+
+    // Differences of the sines and cosines as products of sines.
+    float chord_theta = 2.0 * sin(0.5 * d_theta);
+    float d_cos_theta = -chord_theta * sin(theta + 0.5 * d_theta);
+    float d_sin_theta = chord_theta * cos(theta + 0.5 * d_theta);
+    float chord_phi = 2.0 * sin(0.5 * d_phi);
+    float d_cos_phi = -chord_phi * sin(phi + 0.5 * d_phi);
+    float d_sin_phi = chord_phi * cos(phi + 0.5 * d_phi);
+
+    float a = pow(r, power);
+    float d_a = a * pow_minus_one(d_r / r, power);
+    float cos_phi_w = cos(phi) + d_cos_phi;
+    return vec3(
+        d_a * (cos(theta) + d_cos_theta) * cos_phi_w + a * d_cos_theta * cos_phi_w + a * cos(theta) * d_cos_phi,
+        d_a * (sin(theta) + d_sin_theta) * cos_phi_w + a * d_sin_theta * cos_phi_w + a * sin(theta) * d_cos_phi,
+        d_a * (sin(phi) + d_sin_phi) + a * d_sin_phi
+    );
+}
+
+// `mandel` for the point `delta_c` away from the reference point. The pixel's
+// orbit is point `m` of the reference orbit plus a difference that is carried
+// along exactly. When the reference runs out, when the two end up on
+// different sides of x = 0, or when the pixel's orbit comes closer to the
+// origin than to the reference, the difference is rebased onto the origin,
+// where `bulb_power` is zero and the reference orbit starts over, instead of
+// iterating the pixel's own point at the precision of floats around it.
+float perturbed_mandel(vec3 delta_c, out float trap) {
+    vec3 delta = delta_c;
+    vec3 z = reference(0) + delta;
+    int m = 0;
+    float r = 0.0;
+    float dr = 1.0;
+    float closest = 1e10;
+    float escape = float(bulb_iterations);
+    bool escaped = false;
+    for (int i = 0; i < bulb_iterations; ++i) {
+        r = length(z);
+        if (r > 2.0) {
+            if (!escaped) {
+                escape = float(i) + 1.0 - log(log(r) / log(2.0)) / log(power);
+                escaped = true;
+            }
+            continue;
+        }
+        closest = min(closest, trap_distance(z));
+        dr = pow(r, power - 1.0) * dr * power + 1.0;
+        vec3 reference_point = reference(m);
+        if (m < reference_length && (z.x >= 0.0) == (reference_point.x >= 0.0)
+                && r >= length(delta)) {
+            delta = bulb_power_delta(reference_point, delta, power, phase) + delta_c;
+            m++;
+        } else {
+            delta = bulb_power(z, r, power, phase) + delta_c;
+            m = 0;
+        }
+        z = reference(m) + delta;
+        r = pow(r, power);
+    }
+    trap = colour_value(closest, escape, bulb_iterations);
+    return 0.25 * log(r) * r / dr;
+}
+
+// Which linear piece of the Mandelbox folds `z` is in: the side of the box
+// fold for each component, and the range of the sphere fold.
+vec4 fold_piece(vec3 z) {
+    vec3 folded = clamp(z, -1.0, 1.0) * 2.0 - z;
+    float r2 = dot(folded, folded);
+    return vec4(step(1.0, z) - step(1.0, -z), step(min_rad2, r2) + step(1.0, r2));
+}
+
+// The folds of `mandelbox` applied to `z + delta` minus those applied to `z`,
+// for two points in the same piece of `fold_piece`. The folds are linear
+// there except for the inversion, which is written as a difference.
+vec3 box_fold_delta(vec3 z, vec3 delta) {
+    vec3 folded = clamp(z, -1.0, 1.0) * 2.0 - z;
+    vec3 d = delta * (1.0 - 2.0 * step(1.0, abs(z)));
+    float r2 = dot(folded, folded);
+    if (r2 < min_rad2) {
+        return d;
+    }
+    if (r2 >= 1.0) {
+        return min_rad2 * d;
+    }
+    vec3 w = folded + d;
+    return min_rad2 * (d - folded * (dot(2.0 * folded + d, d) / r2)) / dot(w, w);
+}
+
+// `mandelbox` for the point `delta_c` away from the reference point,
+// following the reference orbit like `perturbed_mandel` and rebasing onto
+// the origin, where the folds are zero, when the reference runs out, the two
+// land in different pieces of the folds, or the pixel's orbit comes closer to
+// the origin than to the reference.
+float perturbed_mandelbox(vec3 delta_c, out float trap) {
+    float abs_scale_m1 = abs(box_scale - 1.0);
+    float abs_scale_raised_to_1m_iters = pow(abs(box_scale), float(1 - box_iterations));
+
+    vec3 delta = delta_c;
+    vec3 z = reference(0) + delta;
+    int m = 0;
+    float w = 1.0;
+    float closest = 1e10;
+    float escape = float(box_iterations);
+
+    for (int i = 0; i < box_iterations; i++) {
+        vec3 folded = clamp(z, -1.0, 1.0) * 2.0 - z;
+        float r2 = dot(folded, folded);
+        float k = clamp(max(min_rad2 / r2, min_rad2), 0.0, 1.0);
+        vec3 reference_point = reference(m);
+        if (m < reference_length && fold_piece(z) == fold_piece(reference_point)
+                && length(z) >= length(delta)) {
+            delta = box_fold_delta(reference_point, delta) * (box_scale / min_rad2) + delta_c;
+            m++;
+        } else {
+            delta = folded * k * (box_scale / min_rad2) + delta_c;
+            m = 0;
+        }
+        z = reference(m) + delta;
+        w = w * k * (abs(box_scale) / min_rad2) + 1.0;
+        closest = min(closest, trap_distance(z));
+        if (r2 > 1000.0) {
+            escape = float(i) + 1.0 - log(r2 / 1000.0) / max(log(box_scale * box_scale), 1e-3);
+            break;
+        }
+    }
+    trap = colour_value(closest, escape, box_iterations);
+    return (length(z) - abs_scale_m1) / w - abs_scale_raised_to_1m_iters;
+}
+
+// The distance estimate at `offset` from the camera. In deep zoom mode the
+// Mandelbulb and Mandelbox are iterated relative to the reference orbit, so
+// detail far below the spacing of floats around the camera survives.
+float scene_distance(vec3 offset, out float trap) {
+    vec3 p = world_position(offset);
+    if (deep_zoom != 0) {
+        vec3 delta_c = reference_offset + offset;
+        if (formula == FORMULA_MANDELBOX) {
+            return perturbed_mandelbox(delta_c, trap);
+        }
+        return min(perturbed_mandel(delta_c, trap), length(p));
+    }
+    return my_mandel(p, trap);
+}
@@ -0,0 +1,215 @@
+use glm::{
+    abs, asin, atan, clamp, clamp_s, cos, dot, length, log, max, min, pow, sin, vec2, vec3, Vec3,
+};
+
+use crate::colouring::OrbitTrap;
+use crate::fractal::{Formula, Fractal};
+use crate::reference_orbit::ReferenceOrbit;
+use crate::scene::Scene;
+use crate::shader::mandelbulb::bulb_power;
+
+/// The distance field as `scene_distance` in `perturbation.glsl` sees it, with
+/// points given relative to the camera.
+pub struct DistanceField<'a> {
+    fractal: &'a Fractal,
+    origin: glm::DVec3,
+    reference: Option<&'a ReferenceOrbit>,
+}
+
+impl<'a> DistanceField<'a> {
+    pub fn new(scene: &'a Scene, reference: Option<&'a ReferenceOrbit>) -> Self {
+        Self {
+            fractal: &scene.fractal,
+            origin: scene.camera.position,
+            reference,
+        }
+    }
+
+    /// The point `offset` away from the camera, rounded once like
+    /// `world_position`.
+    pub fn world_position(&self, offset: &Vec3) -> Vec3 {
+        glm::to_vec3(self.origin + glm::to_dvec3(*offset))
+    }
+
+    /// Port of `scene_distance`. Returns the distance estimate and the palette
+    /// value from `trap`.
+    pub fn distance_and_colour(&self, offset: &Vec3, trap: &OrbitTrap) -> (f32, f32) {
+        let p = self.world_position(offset);
+        let fractal = self.fractal;
+        match self.reference {
+            Some(reference) => {
+                let delta_c = reference.offset + *offset;
+                if fractal.formula == Formula::Mandelbox {
+                    return perturbed_mandelbox(reference, delta_c, fractal, trap);
+                }
+                let (d, value) = perturbed_mandel(reference, delta_c, fractal, trap);
+                (min(d, length(p)), value)
+            }
+            None => fractal.distance_and_colour(&p, trap),
+        }
+    }
+
+    pub fn distance(&self, offset: &Vec3) -> f32 {
+        self.distance_and_colour(offset, &OrbitTrap::default()).0
+    }
+}
+
+/// Port of `pow_minus_one`.
+fn pow_minus_one(u: f32, n: f32) -> f32 {
+    if abs(u) > 1e-2 {
+        return pow(1.0 + u, n) - 1.0;
+    }
+    n * u * (1.0 + (n - 1.0) * u / 2.0 * (1.0 + (n - 2.0) * u / 3.0 * (1.0 + (n - 3.0) * u / 4.0)))
+}
+
+/// Port of `bulb_power_delta`.
+fn bulb_power_delta(z: Vec3, delta: Vec3, power: f32, phase: f32) -> Vec3 {
+    let w = z + delta;
+    let r = length(z);
+    let r_w = length(w);
+    let (z_xy, delta_xy, w_xy) = (vec2(z.x, z.y), vec2(delta.x, delta.y), vec2(w.x, w.y));
+    let rho = length(z_xy);
+    let d_r = dot(z * 2.0 + delta, delta) / (r_w + r);
+    let d_rho = dot(z_xy * 2.0 + delta_xy, delta_xy) / (length(w_xy) + rho);
+
+    let theta = atan(z.y / z.x) * power;
+    let phi = (asin(z.z / r) + phase) * power;
+    let d_theta = atan((z.x * delta.y - z.y * delta.x) / dot(z_xy, w_xy)) * power;
+    let d_phi = asin(clamp((delta.z * rho - d_rho * z.z) / (r * r_w), -1.0, 1.0)) * power;
+
+    let chord_theta = 2.0 * sin(0.5 * d_theta);
+    let d_cos_theta = -chord_theta * sin(theta + 0.5 * d_theta);
+    let d_sin_theta = chord_theta * cos(theta + 0.5 * d_theta);
+    let chord_phi = 2.0 * sin(0.5 * d_phi);
+    let d_cos_phi = -chord_phi * sin(phi + 0.5 * d_phi);
+    let d_sin_phi = chord_phi * cos(phi + 0.5 * d_phi);
+
+    let a = pow(r, power);
+    let d_a = a * pow_minus_one(d_r / r, power);
+    let cos_phi_w = cos(phi) + d_cos_phi;
+    vec3(
+        d_a * (cos(theta) + d_cos_theta) * cos_phi_w
+            + a * d_cos_theta * cos_phi_w
+            + a * cos(theta) * d_cos_phi,
+        d_a * (sin(theta) + d_sin_theta) * cos_phi_w
+            + a * d_sin_theta * cos_phi_w
+            + a * sin(theta) * d_cos_phi,
+        d_a * (sin(phi) + d_sin_phi) + a * d_sin_phi,
+    )
+}
+
+/// Port of `perturbed_mandel`.
+fn perturbed_mandel(
+    reference: &ReferenceOrbit,
+    delta_c: Vec3,
+    fractal: &Fractal,
+    trap: &OrbitTrap,
+) -> (f32, f32) {
+    let (power, iterations) = (fractal.power, fractal.bulb_iterations);
+    let mut delta = delta_c;
+    let mut z = reference.points[0] + delta;
+    let mut m = 0;
+    let mut r: f32 = 0.0;
+    let mut dr = 1.0;
+    let mut closest: f32 = 1e10;
+    let mut escape = iterations as f32;
+    let mut escaped = false;
+    for i in 0..iterations as usize {
+        r = length(z);
+        if r > 2.0 {
+            if !escaped {
+                escape = i as f32 + 1.0 - log(log(r) / log(2.0)) / log(power);
+                escaped = true;
+            }
+            continue;
+        }
+        closest = min(closest, trap.shape.distance(z));
+        dr = pow(r, power - 1.0) * dr * power + 1.0;
+        let reference_point = reference.points[m];
+        if m < reference.len() && (z.x >= 0.0) == (reference_point.x >= 0.0) && r >= length(delta) {
+            delta = bulb_power_delta(reference_point, delta, power, fractal.phase) + delta_c;
+            m += 1;
+        } else {
+            delta = bulb_power(z, r, power, fractal.phase) + delta_c;
+            m = 0;
+        }
+        z = reference.points[m] + delta;
+        r = pow(r, power);
+    }
+    (
+        0.25 * log(r) * r / dr,
+        trap.value(closest, escape, iterations),
+    )
+}
+
+/// Port of `fold_piece`.
+fn fold_piece(z: Vec3, min_rad2: f32) -> [f32; 4] {
+    let folded = clamp_s(z, -1.0, 1.0) * 2.0 - z;
+    let r2 = dot(folded, folded);
+    let side = |c: f32| (c >= 1.0) as u8 as f32 - (-c >= 1.0) as u8 as f32;
+    let range = (r2 >= min_rad2) as u8 as f32 + (r2 >= 1.0) as u8 as f32;
+    [side(z.x), side(z.y), side(z.z), range]
+}
+
+/// Port of `box_fold_delta`.
+fn box_fold_delta(z: Vec3, delta: Vec3, min_rad2: f32) -> Vec3 {
+    let folded = clamp_s(z, -1.0, 1.0) * 2.0 - z;
+    let mirror = |c: f32| if abs(c) >= 1.0 { -1.0 } else { 1.0 };
+    let d = delta * vec3(mirror(z.x), mirror(z.y), mirror(z.z));
+    let r2 = dot(folded, folded);
+    if r2 < min_rad2 {
+        return d;
+    }
+    if r2 >= 1.0 {
+        return d * min_rad2;
+    }
+    let w = folded + d;
+    (d - folded * (dot(folded * 2.0 + d, d) / r2)) * min_rad2 / dot(w, w)
+}
+
+/// Port of `perturbed_mandelbox`.
+fn perturbed_mandelbox(
+    reference: &ReferenceOrbit,
+    delta_c: Vec3,
+    fractal: &Fractal,
+    trap: &OrbitTrap,
+) -> (f32, f32) {
+    let (scale, min_rad2, iterations) = (fractal.scale, fractal.min_rad2, fractal.box_iterations);
+    let abs_scale_m1 = abs(scale - 1.0);
+    let abs_scale_raised_to_1m_iters = pow(abs(scale), 1.0 - iterations as f32);
+
+    let mut delta = delta_c;
+    let mut z = reference.points[0] + delta;
+    let mut m = 0;
+    let mut w: f32 = 1.0;
+    let mut closest: f32 = 1e10;
+    let mut escape = iterations as f32;
+
+    for i in 0..iterations as usize {
+        let folded = clamp_s(z, -1.0, 1.0) * 2.0 - z;
+        let r2 = dot(folded, folded);
+        let k = clamp(max(min_rad2 / r2, min_rad2), 0.0, 1.0);
+        let reference_point = reference.points[m];
+        if m < reference.len()
+            && fold_piece(z, min_rad2) == fold_piece(reference_point, min_rad2)
+            && length(z) >= length(delta)
+        {
+            delta = box_fold_delta(reference_point, delta, min_rad2) * (scale / min_rad2) + delta_c;
+            m += 1;
+        } else {
+            delta = folded * k * (scale / min_rad2) + delta_c;
+            m = 0;
+        }
+        z = reference.points[m] + delta;
+        w = w * k * (abs(scale) / min_rad2) + 1.0;
+        closest = min(closest, trap.shape.distance(z));
+        if r2 > 1000.0 {
+            escape = i as f32 + 1.0 - log(r2 / 1000.0) / max(log(scale * scale), 1e-3);
+            break;
+        }
+    }
+    (
+        (length(z) - abs_scale_m1) / w - abs_scale_raised_to_1m_iters,
+        trap.value(closest, escape, iterations),
+    )
+}
@@ -0,0 +1,10 @@
+// Shows the samples averaged in a framebuffer's texture by stretching the
+// part of it they fill over the viewport.
+
+uniform sampler2D accumulation;
+// Takes window coordinates to texture coordinates.
+uniform vec2 texel_scale;
+
+void main() {
+    FRAG_COLOR = texture(accumulation, gl_FragCoord.xy * texel_scale);
+}
@@ -0,0 +1,12 @@
+use glm::{abs, length, max, max_s, min, Vec3};
+
+/// Port of `sphere` in `distance.glsl`.
+pub fn sphere(p: &Vec3, c: &Vec3, r: f32) -> f32 {
+    length(*c - *p) - r
+}
+
+/// Port of `sdRoundBox` in `distance.glsl`.
+pub fn sd_round_box(p: &Vec3, b: &Vec3, r: f32) -> f32 {
+    let q = abs(*p) - *b + r;
+    length(max_s(q, 0.0)) + min(max(q.x, max(q.y, q.z)), 0.0) - r
+}
@@ -0,0 +1,113 @@
+uniform sampler2D palette;
+
+// The surface colour for a palette value from `scene_distance`.
+vec3 palette_colour(float value) {
+    float t = value * palette_scale + palette_offset;
+    return surface_color * texture(palette, vec2(t, 0.5)).rgb;
+}
+
+// Shadow rays give up once they are this far from the surface.
+#define SHADOW_DISTANCE 8.0
+
+// Fraction of the sun visible from the hit at `p` with unit normal `n`, where
+// `p` is relative to the camera like all positions passed around here. The
+// shadow ray keeps the smallest `shadow_sharpness * d / t` seen along the way,
+// so rays passing close to the surface end up in a penumbra.
+float soft_shadow(vec3 p, vec3 n) {
+    vec3 l = -light_dir;
+    vec3 start = p + n * (2.0 * stop_distance);
+    float visible = 1.0;
+    float t = stop_distance;
+
+    for (int i = 0; i < shadow_steps; i++) {
+        float trap;
+        float d = scene_distance(start + l * t, trap);
+        if (d < stop_distance) {
+            return 0.0;
+        }
+        visible = min(visible, shadow_sharpness * d / t);
+        t += d;
+        if (t > SHADOW_DISTANCE) {
+            break;
+        }
+    }
+
+    return visible;
+}
+
+// How much of the ambient light reaches the hit at `p` with unit normal `n`.
+// Points sampled along the normal that are closer to the surface than to `p`
+// are in a crevice, the closer the darker.
+float ambient_occlusion(vec3 p, vec3 n) {
+    if (occlusion_samples == 0) {
+        return 1.0;
+    }
+
+    float occlusion = 0.0;
+    float total = 0.0;
+    float weight = 1.0;
+    for (int i = 1; i <= occlusion_samples; i++) {
+        float h = occlusion_radius * float(i) / float(occlusion_samples);
+        float trap;
+        float d = scene_distance(p + n * h, trap);
+        occlusion += weight * max(h - d, 0.0) / h;
+        total += weight;
+        weight *= 0.5;
+    }
+
+    return clamp(1.0 - occlusion_strength * occlusion / total, 0.0, 1.0);
+}
+
+// Darkening from the number of march steps a hit took. Rays that graze the
+// surface or squeeze into holes need many steps, which is a cheap hint of
+// occlusion.
+float step_occlusion(float steps) {
+    return clamp(1.0 - step_occlusion_strength * steps / float(max_steps), 0.0, 1.0);
+}
+
+// Lights a hit with albedo `albedo` and unit normal `n`, seen along the unit
+// direction `view`. The sun's diffuse and specular light is scaled by
+// `shadow` and the ambient light by `occlusion`, the ambient light and
+// reflections come from the background. Disabled terms have a weight of
+// zero, see `Shading` in scene.rs.
+vec3 shade(vec3 albedo, vec3 n, vec3 view, float shadow, float occlusion) {
+    vec3 l = -light_dir;
+    vec3 v = -view;
+    float n_dot_l = max(dot(n, l), 0.0);
+
+    vec3 color = occlusion * ambient * albedo * ambient_light(n);
+    color += shadow * diffuse * n_dot_l * albedo * light_color;
+    if (n_dot_l > 0.0) {
+        vec3 h = normalize(l + v);
+        color += shadow * specular * pow(max(dot(n, h), 0.0), shininess) * light_color;
+    }
+    color += rim * pow(1.0 - max(dot(n, v), 0.0), rim_power) * light_color;
+    if (reflection > 0.0) {
+        color += reflection * background(reflect(view, n));
+    }
+    return color;
+}
+
+// Fraction of the light from a hit `t` away along the unit direction `dir`
+// that the fog replaces with its own colour. The density falls off
+// exponentially above the world height `fog_height`, which integrated along
+// the ray from the camera gives a closed form, see
+// https://iquilezles.org/articles/fog/
+float fog_amount(vec3 dir, float t) {
+    float optical_depth = fog_density * t;
+    float k = fog_falloff * dir.y * t;
+    if (abs(k) > 1e-4) {
+        optical_depth *= (1.0 - exp(-k)) / k;
+    }
+    float height = origin.y + origin_low.y;
+    optical_depth *= exp(fog_falloff * (fog_height - height));
+    return 1.0 - exp(-optical_depth);
+}
+
+// Glow from the march of a ray that took `steps` steps over a distance `t`.
+// Rays passing close to the surface slow down and take many, so it shows as
+// a halo, which builds up over `glow_depth` like light scattered in a medium.
+vec3 glow_light(float steps, float t) {
+    float depth = glow_depth > 0.0 ? 1.0 - exp(-t / glow_depth) : 1.0;
+    return glow * (steps / float(max_steps) * depth);
+}
@@ -0,0 +1,194 @@
+use glm::{abs, clamp, dot, exp, max, min, normalize, pow, reflect};
+
+use crate::light::SunLight;
+use crate::scene::{Atmosphere, Scene, Shading};
+use crate::shader::perturbation::DistanceField;
+
+const SHADOW_DISTANCE: f32 = 8.0;
+
+/// Port of `soft_shadow` in `shading.glsl`.
+pub fn soft_shadow(
+    field: &DistanceField,
+    p: glm::Vec3,
+    n: glm::Vec3,
+    stop_distance: f32,
+    light: &SunLight,
+    shading: &Shading,
+) -> f32 {
+    let l = -light.direction;
+    let start = p + n * (2.0 * stop_distance);
+    let mut visible: f32 = 1.0;
+    let mut t = stop_distance;
+
+    for _ in 0..ShadingWeights::new(shading).shadow_steps {
+        let d = field.distance(&(start + l * t));
+        if d < stop_distance {
+            return 0.0;
+        }
+        visible = min(visible, shading.shadow_sharpness * d / t);
+        t += d;
+        if t > SHADOW_DISTANCE {
+            break;
+        }
+    }
+
+    visible
+}
+
+/// Port of `ambient_occlusion` in `shading.glsl`, for a camera with the
+/// given focal distance.
+pub fn ambient_occlusion(
+    field: &DistanceField,
+    p: glm::Vec3,
+    n: glm::Vec3,
+    shading: &Shading,
+    focal_distance: f32,
+) -> f32 {
+    let weights = ShadingWeights::new(shading);
+    let samples = weights.occlusion_samples;
+    if samples == 0 {
+        return 1.0;
+    }
+
+    let mut occlusion = 0.0;
+    let mut total = 0.0;
+    let mut weight = 1.0;
+    for i in 1..=samples {
+        let h = shading.occlusion_radius * focal_distance * i as f32 / samples as f32;
+        let d = field.distance(&(p + n * h));
+        occlusion += weight * max(h - d, 0.0) / h;
+        total += weight;
+        weight *= 0.5;
+    }
+
+    clamp(1.0 - weights.occlusion * occlusion / total, 0.0, 1.0)
+}
+
+/// Port of `step_occlusion` in `shading.glsl`.
+pub fn step_occlusion(steps: u32, max_steps: u32, shading: &Shading) -> f32 {
+    let strength = ShadingWeights::new(shading).step_occlusion;
+    clamp(1.0 - strength * steps as f32 / max_steps as f32, 0.0, 1.0)
+}
+
+/// Port of `shade` in `shading.glsl`.
+pub fn shade(
+    albedo: glm::Vec3,
+    n: glm::Vec3,
+    view: glm::Vec3,
+    shadow: f32,
+    occlusion: f32,
+    scene: &Scene,
+) -> glm::Vec3 {
+    let (light, shading, background) = (&scene.light, &scene.shading, &scene.background);
+    let weights = ShadingWeights::new(shading);
+    let l = -light.direction;
+    let v = -view;
+    let n_dot_l = max(dot(n, l), 0.0);
+
+    let mut color = albedo * background.ambient_light(n) * (occlusion * weights.ambient);
+    color = color + albedo * light.color * (shadow * weights.diffuse * n_dot_l);
+    if n_dot_l > 0.0 {
+        let h = normalize(l + v);
+        color = color
+            + light.color
+                * (shadow * weights.specular * pow(max(dot(n, h), 0.0), shading.shininess));
+    }
+    color = color + light.color * (weights.rim * pow(1.0 - max(dot(n, v), 0.0), shading.rim_power));
+    if weights.reflection > 0.0 {
+        color = color + background.color(reflect(view, n), light) * weights.reflection;
+    }
+    color
+}
+
+/// Port of `fog_amount` in `shading.glsl`, for a camera at the world height
+/// `height`.
+pub fn fog_amount(height: f32, dir: glm::Vec3, t: f32, weights: &AtmosphereWeights) -> f32 {
+    let mut optical_depth = weights.fog_density * t;
+    let k = weights.fog_falloff * dir.y * t;
+    if abs(k) > 1e-4 {
+        optical_depth *= (1.0 - exp(-k)) / k;
+    }
+    optical_depth *= exp(weights.fog_falloff * (weights.fog_height - height));
+    1.0 - exp(-optical_depth)
+}
+
+/// Port of `glow_light` in `shading.glsl`.
+pub fn glow_light(steps: u32, max_steps: u32, t: f32, weights: &AtmosphereWeights) -> glm::Vec3 {
+    let depth = if weights.glow_depth > 0.0 {
+        1.0 - exp(-t / weights.glow_depth)
+    } else {
+        1.0
+    };
+    weights.glow * (steps as f32 / max_steps as f32 * depth)
+}
+
+/// The glow colour scaled by its strength, and the lengths of the glow and fog
+/// in world units for a camera with the given focal distance, zero for the
+/// effects that are off. These are the values the shader gets as uniforms.
+pub struct AtmosphereWeights {
+    pub glow: glm::Vec3,
+    pub glow_depth: f32,
+    pub fog_density: f32,
+    pub fog_falloff: f32,
+    pub fog_height: f32,
+}
+
+impl AtmosphereWeights {
+    pub fn new(atmosphere: &Atmosphere, focal_distance: f32) -> Self {
+        Self {
+            glow: if atmosphere.glow {
+                atmosphere.glow_color * atmosphere.glow_strength
+            } else {
+                glm::vec3(0.0, 0.0, 0.0)
+            },
+            glow_depth: atmosphere.glow_depth * focal_distance,
+            fog_density: if atmosphere.fog {
+                atmosphere.fog_density / focal_distance
+            } else {
+                0.0
+            },
+            fog_falloff: atmosphere.fog_falloff / focal_distance,
+            fog_height: atmosphere.fog_height * focal_distance,
+        }
+    }
+}
+
+/// The weight of each term in `shade` and of the occlusion, zero for the
+/// disabled ones, and the sample counts, zero when the effect is off. These
+/// are the values the shader gets as uniforms.
+pub struct ShadingWeights {
+    pub ambient: f32,
+    pub diffuse: f32,
+    pub specular: f32,
+    pub rim: f32,
+    pub shadow_steps: u32,
+    pub occlusion: f32,
+    pub occlusion_samples: u32,
+    pub step_occlusion: f32,
+    pub reflection: f32,
+}
+
+impl ShadingWeights {
+    pub fn new(shading: &Shading) -> Self {
+        let weight = |enabled: bool, strength: f32| if enabled { strength } else { 0.0 };
+        Self {
+            ambient: weight(shading.ambient, shading.ambient_strength),
+            diffuse: weight(shading.diffuse, 1.0),
+            specular: weight(shading.specular, shading.specular_strength),
+            rim: weight(shading.rim, shading.rim_strength),
+            shadow_steps: if shading.shadows {
+                shading.shadow_steps
+            } else {
+                0
+            },
+            occlusion: weight(shading.occlusion, shading.occlusion_strength),
+            occlusion_samples: if shading.occlusion {
+                shading.occlusion_samples
+            } else {
+                0
+            },
+            step_occlusion: weight(shading.step_occlusion, shading.step_occlusion_strength),
+            reflection: weight(shading.reflection, shading.reflection_strength),
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use super::version::GlslVersion;
+use crate::renderer::ShaderStage;
+
+/// The files making up each shader, in order, with their embedded contents.
+/// None of them has a `#version` line, that comes from
+/// `GlslVersion::preamble`.
+const VERTEX_SHADER_FILES: [(&str, &str); 1] = [("vertex.glsl", include_str!("vertex.glsl"))];
+
+const FRAGMENT_SHADER_FILES: [(&str, &str); 6] = [
+    ("common.glsl", include_str!("common.glsl")),
+    ("distance.glsl", include_str!("distance.glsl")),
+    ("perturbation.glsl", include_str!("perturbation.glsl")),
+    ("background.glsl", include_str!("background.glsl")),
+    ("shading.glsl", include_str!("shading.glsl")),
+    ("mandelbulb.glsl", include_str!("mandelbulb.glsl")),
+];
+
+/// The fragment shader that shows averaged samples, see `Renderer::present`.
+const PRESENT_SHADER_FILES: [(&str, &str); 1] = [("present.glsl", include_str!("present.glsl"))];
+
+fn files(stage: ShaderStage) -> &'static [(&'static str, &'static str)] {
+    match stage {
+        ShaderStage::Vertex => &VERTEX_SHADER_FILES,
+        ShaderStage::Fragment => &FRAGMENT_SHADER_FILES,
+    }
+}
+
+/// The uniform block and the distance estimators, without the march loop, so
+/// they can be compiled into other shaders.
+#[cfg(test)]
+pub const DISTANCE_SHADER_SOURCE: &str = concat!(
+    include_str!("common.glsl"),
+    include_str!("distance.glsl"),
+    include_str!("perturbation.glsl")
+);
+
+/// Where the shader files live in the source tree, for reloading them while
+/// the program runs.
+pub fn shader_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("src/shader")
+}
+
+struct Part {
+    name: String,
+    text: String,
+}
+
+/// A shader assembled from several files. Each file starts with a `#line`
+/// directive, so the driver reports errors with the file index as the source
+/// string number and the line within that file.
+pub struct ShaderSource {
+    stage: ShaderStage,
+    parts: Vec<Part>,
+}
+
+impl ShaderSource {
+    /// The shader compiled into the binary.
+    pub fn embedded(stage: ShaderStage) -> Self {
+        Self::from_files(stage, files(stage))
+    }
+
+    /// The fragment shader that shows the samples averaged in a framebuffer.
+    /// It is always the embedded one.
+    pub fn present() -> Self {
+        Self::from_files(ShaderStage::Fragment, &PRESENT_SHADER_FILES)
+    }
+
+    fn from_files(stage: ShaderStage, files: &[(&str, &str)]) -> Self {
+        let parts = files
+            .iter()
+            .map(|(name, text)| Part {
+                name: format!("src/shader/{name}"),
+                text: text.to_string(),
+            })
+            .collect();
+        Self { stage, parts }
+    }
+
+    /// Reads the shader files from `dir`.
+    pub fn load(dir: &Path, stage: ShaderStage) -> io::Result<Self> {
+        let parts = files(stage)
+            .iter()
+            .map(|(name, _)| {
+                let path = dir.join(name);
+                Ok(Part {
+                    text: fs::read_to_string(&path)?,
+                    name: path.display().to_string(),
+                })
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(Self { stage, parts })
+    }
+
+    pub fn stage(&self) -> ShaderStage {
+        self.stage
+    }
+
+    /// The complete source for compiling as `version`.
+    pub fn to_cstring(&self, version: GlslVersion) -> CString {
+        let mut source = version.preamble(self.stage);
+        for (i, part) in self.parts.iter().enumerate() {
+            source.push_str(&version.line_directive(1, i));
+            source.push_str(&part.text);
+            if !part.text.ends_with('\n') {
+                source.push('\n');
+            }
+        }
+        CString::new(source).expect("shader source contains a NUL byte")
+    }
+
+    /// Rewrites the locations in a compiler info log as `file:line` and quotes
+    /// the offending line. Lines without a recognised location are kept as is.
+    pub fn annotate_log(&self, log: &str) -> String {
+        let mut annotated = String::new();
+        for message in log.lines().filter(|line| !line.trim().is_empty()) {
+            match parse_location(message) {
+                Some((string, line)) if string < self.parts.len() && line > 0 => {
+                    let part = &self.parts[string];
+                    let code = part.text.lines().nth(line - 1).unwrap_or("").trim();
+                    annotated.push_str(&format!("{}:{line}: {message}\n    {code}\n", part.name));
+                }
+                _ => annotated.push_str(&format!("{message}\n")),
+            }
+        }
+        annotated
+    }
+}
+
+/// Finds the source string number and line in a compiler message. Drivers
+/// disagree on the format: Mesa writes `0:12(5): error`, NVIDIA writes
+/// `0(12) : error` and AMD writes `ERROR: 0:12: ...`.
+fn parse_location(message: &str) -> Option<(usize, usize)> {
+    let rest = message
+        .trim_start_matches("ERROR: ")
+        .trim_start_matches("WARNING: ");
+    let string_end = rest.find(|c: char| !c.is_ascii_digit())?;
+    let string = rest[..string_end].parse().ok()?;
+    let rest = rest[string_end..].strip_prefix([':', '('])?;
+    let line_end = rest.find(|c: char| !c.is_ascii_digit())?;
+    let line = rest[..line_end].parse().ok()?;
+    Some((string, line))
+}
+
+/// Polls the fragment shader files for changes. The vertex shader is always
+/// the embedded one.
+pub struct ShaderWatcher {
+    dir: PathBuf,
+    last_modified: Option<SystemTime>,
+}
+
+impl ShaderWatcher {
+    pub fn new(dir: PathBuf) -> Self {
+        let mut watcher = Self {
+            dir,
+            last_modified: None,
+        };
+        watcher.last_modified = watcher.modified();
+        watcher
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        FRAGMENT_SHADER_FILES
+            .iter()
+            .filter_map(|(name, _)| fs::metadata(self.dir.join(name)).ok()?.modified().ok())
+            .max()
+    }
+
+    /// Returns the new source if any of the files changed since the last call.
+    pub fn poll(&mut self) -> Option<io::Result<ShaderSource>> {
+        let modified = self.modified();
+        if modified == self.last_modified {
+            return None;
+        }
+        self.last_modified = modified;
+        Some(ShaderSource::load(&self.dir, ShaderStage::Fragment))
+    }
+}
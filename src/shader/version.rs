@@ -0,0 +1,101 @@
+use crate::renderer::ShaderStage;
+
+/// The GLSL dialect the shaders are compiled as, picked from the context the
+/// renderer runs on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GlslVersion {
+    /// GLSL ES 3.00, for GLES 3 contexts.
+    Es300,
+    /// GLSL 3.30, for desktop GL 3.3 and newer.
+    Core330,
+    /// GLSL 1.20, for desktop GL 2.1 up to 3.2. It has no uniform blocks.
+    Legacy120,
+}
+
+impl GlslVersion {
+    /// Picks the dialect from a `GL_VERSION` string such as
+    /// `4.5 (Core Profile) Mesa 22.3.6` or `OpenGL ES 3.2 Mesa 22.3.6`, or
+    /// `None` for GLES 2, whose GLSL ES 1.00 none of these are.
+    pub fn detect(gl_version: &str) -> Option<Self> {
+        let es = gl_version.strip_prefix("OpenGL ES");
+        let mut numbers = es
+            .unwrap_or(gl_version)
+            .split(|c: char| !c.is_ascii_digit())
+            .filter_map(|number| number.parse::<u32>().ok());
+        let version = (numbers.next().unwrap_or(0), numbers.next().unwrap_or(0));
+        if es.is_some() {
+            return (version >= (3, 0)).then_some(GlslVersion::Es300);
+        }
+        if version >= (3, 3) {
+            Some(GlslVersion::Core330)
+        } else {
+            Some(GlslVersion::Legacy120)
+        }
+    }
+
+    /// Whether the uniforms live in a uniform block backed by a buffer, or
+    /// are plain uniforms set one by one.
+    pub fn uses_uniform_block(self) -> bool {
+        self != GlslVersion::Legacy120
+    }
+
+    /// The `#version` line and the macros the shader files are written
+    /// against. They use `ATTRIBUTE` and `VARYING` for the stage inputs and
+    /// outputs, `FRAG_COLOR` for the fragment colour, and declare uniforms
+    /// with `UNIFORM_BLOCK_BEGIN(name)`, `UNIFORM(kind, name)` and
+    /// `UNIFORM_BLOCK_END`. Textures are read with `texture`.
+    pub fn preamble(self, stage: ShaderStage) -> String {
+        let mut lines = vec![match self {
+            // Marching needs the precision, mediump runs out of bits long
+            // before the surface is reached.
+            GlslVersion::Es300 => "#version 300 es\nprecision highp float;",
+            GlslVersion::Core330 => "#version 330 core",
+            GlslVersion::Legacy120 => "#version 120\n#define texture texture2D",
+        }];
+
+        let modern = self != GlslVersion::Legacy120;
+        lines.extend_from_slice(match (stage, modern) {
+            (ShaderStage::Vertex, true) => &["#define ATTRIBUTE in", "#define VARYING out"][..],
+            (ShaderStage::Vertex, false) => {
+                &["#define ATTRIBUTE attribute", "#define VARYING varying"]
+            }
+            (ShaderStage::Fragment, true) => &[
+                "#define VARYING in",
+                "out vec4 frag_color;",
+                "#define FRAG_COLOR frag_color",
+            ],
+            (ShaderStage::Fragment, false) => {
+                &["#define VARYING varying", "#define FRAG_COLOR gl_FragColor"]
+            }
+        });
+
+        lines.extend_from_slice(if self.uses_uniform_block() {
+            &[
+                "#define UNIFORM_BLOCK_BEGIN(name) layout(std140) uniform name {",
+                "#define UNIFORM(kind, name) kind name;",
+                "#define UNIFORM_BLOCK_END };",
+            ]
+        } else {
+            &[
+                "#define UNIFORM_BLOCK_BEGIN(name)",
+                "#define UNIFORM(kind, name) uniform kind name;",
+                "#define UNIFORM_BLOCK_END",
+            ]
+        });
+
+        let mut preamble = lines.join("\n");
+        preamble.push('\n');
+        preamble
+    }
+
+    /// A `#line` directive that makes the next line number `line` of source
+    /// string `string`. Before GLSL 3.30 the line after the directive was
+    /// numbered `line + 1`, GLSL ES has always used `line`.
+    pub fn line_directive(self, line: usize, string: usize) -> String {
+        let line = match self {
+            GlslVersion::Legacy120 => line - 1,
+            GlslVersion::Es300 | GlslVersion::Core330 => line,
+        };
+        format!("#line {line} {string}\n")
+    }
+}
@@ -0,0 +1,9 @@
+ATTRIBUTE vec2 position;
+ATTRIBUTE vec3 ray;
+
+VARYING vec3 ray_direction;
+
+void main() {
+    gl_Position = vec4(position, 0.0, 1.0);
+    ray_direction = ray;
+}
@@ -0,0 +1,49 @@
+use crate::camera;
+
+/// A rectangle of a larger image in pixels, with `y` counted from the top row
+/// like `Image`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Tile {
+    /// Splits a `width` x `height` image into tiles of at most `size` x `size`
+    /// pixels, row by row from the top left. Tiles on the right and bottom
+    /// edges are cut to fit.
+    pub fn split(width: u32, height: u32, size: u32) -> Vec<Tile> {
+        let size = size.max(1);
+        let mut tiles = Vec::new();
+        for y in (0..height).step_by(size as usize) {
+            for x in (0..width).step_by(size as usize) {
+                tiles.push(Tile {
+                    x,
+                    y,
+                    width: size.min(width - x),
+                    height: size.min(height - y),
+                });
+            }
+        }
+        tiles
+    }
+
+    /// The corner rays of the part of the frustum this tile covers, in the
+    /// order of `Camera::get_corners`, given those of the whole
+    /// `width` x `height` image. Neighbouring tiles share the rays along their
+    /// common edge, so the stitched image has no seams.
+    pub fn corners(&self, corners: &[glm::Vec3; 4], width: u32, height: u32) -> [glm::Vec3; 4] {
+        let left = self.x as f32 / width as f32;
+        let right = (self.x + self.width) as f32 / width as f32;
+        let top = 1.0 - self.y as f32 / height as f32;
+        let bottom = 1.0 - (self.y + self.height) as f32 / height as f32;
+        [
+            camera::ray_direction(corners, left, bottom),
+            camera::ray_direction(corners, left, top),
+            camera::ray_direction(corners, right, bottom),
+            camera::ray_direction(corners, right, top),
+        ]
+    }
+}
@@ -0,0 +1,326 @@
+use std::ops::{Add, Mul, Sub};
+
+use glm::DVec3;
+
+use crate::quaternion::Quaternion;
+
+/// Where the camera is and which way it faces, as in `Camera`. The camera
+/// looks at the focus `focal_distance` in front of `position`, and the
+/// focal distance is the zoom.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraState {
+    pub position: DVec3,
+    /// Turns -z to the direction the camera looks in and y to the top of
+    /// the screen.
+    pub orientation: Quaternion,
+    pub focal_distance: f64,
+}
+
+/// A camera state at a time in seconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Keyframe {
+    pub time: f64,
+    pub state: CameraState,
+}
+
+/// Camera states to pass through at given times, with smooth motion in
+/// between.
+///
+/// Rather than the position, the track interpolates the focus point the
+/// camera looks at, the direction it looks in and the logarithm of the zoom.
+/// The camera's position follows from those. That way a dive towards a point
+/// keeps it in view while the zoom changes by many orders of magnitude,
+/// where interpolating positions would let it drift off the screen.
+///
+/// The focus, the zoom and the angle turned so far follow Catmull-Rom
+/// splines through the keyframes, and the orientation turns the short way
+/// between keyframes about a fixed axis. The tangents are limited to the
+/// slopes of the segments on either side, so no segment strays further than
+/// its own length. Otherwise the motion of a wide segment would throw the
+/// camera far off in the tiny ones next to it. The track starts and ends at
+/// rest.
+#[derive(Debug, Clone)]
+pub struct KeyframeTrack {
+    times: Vec<f64>,
+    poses: Vec<Pose>,
+    /// The angle the orientation has turned through at each keyframe.
+    turned: Vec<f64>,
+}
+
+/// `CameraState` in the terms the track interpolates.
+#[derive(Debug, Clone, Copy)]
+struct Pose {
+    focus: DVec3,
+    orientation: Quaternion,
+    log_zoom: f64,
+}
+
+impl CameraState {
+    /// The camera at `position` looking along `forward`, whose length is the
+    /// focal distance, with the top of the screen towards `up`.
+    pub fn looking_along(position: DVec3, forward: DVec3, up: DVec3) -> Self {
+        Self {
+            position,
+            orientation: Quaternion::looking_along(forward, up),
+            focal_distance: glm::length(forward),
+        }
+    }
+
+    /// From `position` to the focus.
+    pub fn forward(&self) -> DVec3 {
+        self.orientation
+            .rotate(glm::dvec3(0.0, 0.0, -self.focal_distance))
+    }
+
+    /// The state a fraction `t` of the way from `self` to `other`, with the
+    /// focus moving in a straight line, the orientation turning at a steady
+    /// rate and the zoom changing by the same factor in equal steps.
+    pub fn interpolate(&self, other: &CameraState, t: f64) -> CameraState {
+        let (a, b) = (Pose::new(self), Pose::new(other));
+        Pose {
+            focus: a.focus + (b.focus - a.focus) * t,
+            orientation: a.orientation.slerp(&b.orientation, t),
+            log_zoom: a.log_zoom + (b.log_zoom - a.log_zoom) * t,
+        }
+        .state()
+    }
+}
+
+impl Pose {
+    fn new(state: &CameraState) -> Self {
+        Self {
+            focus: state.position + state.forward(),
+            orientation: state.orientation,
+            log_zoom: state.focal_distance.ln(),
+        }
+    }
+
+    fn state(&self) -> CameraState {
+        let state = CameraState {
+            position: self.focus,
+            orientation: self.orientation,
+            focal_distance: self.log_zoom.exp(),
+        };
+        CameraState {
+            position: self.focus - state.forward(),
+            ..state
+        }
+    }
+}
+
+impl KeyframeTrack {
+    /// A track through `keyframes`, whose times have to increase.
+    pub fn new(keyframes: &[Keyframe]) -> Result<Self, &'static str> {
+        if keyframes.is_empty() {
+            return Err("a keyframe track needs at least one keyframe");
+        }
+        if keyframes.windows(2).any(|k| k[1].time <= k[0].time) {
+            return Err("keyframe times have to increase");
+        }
+        if keyframes.iter().any(|k| k.state.focal_distance <= 0.0) {
+            return Err("keyframe focal distances have to be above zero");
+        }
+        let poses: Vec<_> = keyframes.iter().map(|k| Pose::new(&k.state)).collect();
+        let mut turned = vec![0.0];
+        for pair in poses.windows(2) {
+            let angle = pair[0].orientation.angle_to(&pair[1].orientation);
+            turned.push(turned[turned.len() - 1] + angle);
+        }
+        Ok(Self {
+            times: keyframes.iter().map(|k| k.time).collect(),
+            poses,
+            turned,
+        })
+    }
+
+    /// The time of the last keyframe.
+    pub fn duration(&self) -> f64 {
+        self.times[self.times.len() - 1]
+    }
+
+    /// The camera state at `time`. Before the first keyframe and after the
+    /// last the camera stays there.
+    pub fn sample(&self, time: f64) -> CameraState {
+        let next = self.times.partition_point(|&t| t <= time);
+        if next == 0 || next == self.times.len() {
+            return self.poses[next.saturating_sub(1)].state();
+        }
+        let i = next - 1;
+        let t = (time - self.times[i]) / (self.times[next] - self.times[i]);
+        let focus = self.spline(i, t, |pose, _| pose.focus);
+        let log_zoom = self.spline(i, t, |pose, _| pose.log_zoom);
+
+        let (a, b) = (&self.poses[i], &self.poses[next]);
+        let segment_turn = self.turned[next] - self.turned[i];
+        let turn = if segment_turn > 0.0 {
+            (self.spline(i, t, |_, j| self.turned[j]) - self.turned[i]) / segment_turn
+        } else {
+            t
+        };
+        Pose {
+            focus,
+            orientation: a.orientation.slerp(&b.orientation, turn),
+            log_zoom,
+        }
+        .state()
+    }
+
+    /// `value` of the keyframes, which gets their pose and index, on the
+    /// spline a fraction `t` of the way through segment `i`.
+    fn spline<T: Value>(&self, i: usize, t: f64, value: impl Fn(&Pose, usize) -> T) -> T {
+        let dt = self.times[i + 1] - self.times[i];
+        hermite(
+            value(&self.poses[i], i),
+            self.tangent(i, &value) * dt,
+            value(&self.poses[i + 1], i + 1),
+            self.tangent(i + 1, &value) * dt,
+            t,
+        )
+    }
+
+    /// The rate of change of `value` at keyframe `i`: the Catmull-Rom
+    /// tangent, shortened to the slopes of the segments on either side. It
+    /// is zero at the ends.
+    fn tangent<T: Value>(&self, i: usize, value: &impl Fn(&Pose, usize) -> T) -> T {
+        let p1 = value(&self.poses[i], i);
+        if i == 0 || i + 1 >= self.poses.len() {
+            return p1 * 0.0;
+        }
+        let p0 = value(&self.poses[i - 1], i - 1);
+        let p2 = value(&self.poses[i + 1], i + 1);
+        let (t0, t1, t2) = (self.times[i - 1], self.times[i], self.times[i + 1]);
+        let tangent = (p2 - p0) * (1.0 / (t2 - t0));
+        let limit = f64::min(
+            (p1 - p0).magnitude() / (t1 - t0),
+            (p2 - p1).magnitude() / (t2 - t1),
+        );
+        let length = tangent.magnitude();
+        if length > limit {
+            tangent * (limit / length)
+        } else {
+            tangent
+        }
+    }
+}
+
+/// What the track can put on a spline.
+trait Value: Copy + Add<Output = Self> + Sub<Output = Self> + Mul<f64, Output = Self> {
+    fn magnitude(self) -> f64;
+}
+
+impl Value for f64 {
+    fn magnitude(self) -> f64 {
+        self.abs()
+    }
+}
+
+impl Value for DVec3 {
+    fn magnitude(self) -> f64 {
+        glm::length(self)
+    }
+}
+
+/// The cubic Hermite curve from `p0` with tangent `m0` to `p1` with tangent
+/// `m1`, at `t` from 0 to 1.
+fn hermite<T: Value>(p0: T, m0: T, p1: T, m1: T, t: f64) -> T {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    p0 * (2.0 * t3 - 3.0 * t2 + 1.0)
+        + m0 * (t3 - 2.0 * t2 + t)
+        + p1 * (3.0 * t2 - 2.0 * t3)
+        + m1 * (t3 - t2)
+}
+
+#[cfg(test)]
+mod tests {
+    use glm::dvec3;
+
+    use super::*;
+
+    fn keyframe(time: f64, position: DVec3, direction: DVec3, focal_distance: f64) -> Keyframe {
+        let forward = glm::normalize(direction) * focal_distance;
+        Keyframe {
+            time,
+            state: CameraState::looking_along(position, forward, dvec3(0.0, 1.0, 0.0)),
+        }
+    }
+
+    /// A dive towards a point near the origin that turns on the way, zooms in
+    /// by three orders of magnitude in a second and then drifts in slowly.
+    fn dive() -> Vec<Keyframe> {
+        vec![
+            keyframe(0.0, dvec3(0.0, 0.0, 3.0), dvec3(0.0, 0.0, -1.0), 2.0),
+            keyframe(2.0, dvec3(1.0, 0.5, 1.5), dvec3(-0.5, -0.3, -1.0), 0.1),
+            keyframe(3.0, dvec3(0.1, 0.2, 1.0), dvec3(0.0, -1.0, -1.0), 1e-4),
+            keyframe(20.0, dvec3(0.1, 0.2, 1.0), dvec3(1.0, 0.0, -1.0), 5e-5),
+        ]
+    }
+
+    fn distance(a: DVec3, b: DVec3) -> f64 {
+        glm::length(a - b)
+    }
+
+    #[test]
+    fn passes_through_the_keyframes() {
+        let keyframes = dive();
+        let track = KeyframeTrack::new(&keyframes).unwrap();
+        for keyframe in &keyframes {
+            let state = track.sample(keyframe.time);
+            let expected = keyframe.state;
+            let scale = expected.focal_distance;
+            assert!(distance(state.position, expected.position) < 1e-9 * scale);
+            assert!(state.orientation.angle_to(&expected.orientation) < 1e-9);
+            assert!((state.focal_distance / scale - 1.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn starts_and_ends_at_rest() {
+        let keyframes = dive();
+        let track = KeyframeTrack::new(&keyframes).unwrap();
+        let dt = 1e-4;
+        for (time, towards) in [(0.0, dt), (track.duration(), track.duration() - dt)] {
+            let (a, b) = (track.sample(time), track.sample(towards));
+            let scale = a.focal_distance;
+            // Moving off at any speed would cover about `dt` of the scale.
+            assert!(distance(a.position, b.position) < 1e-2 * dt * scale);
+            assert!(a.orientation.angle_to(&b.orientation) < 1e-2 * dt);
+            assert!((b.focal_distance / a.focal_distance).ln().abs() < 1e-2 * dt);
+        }
+    }
+
+    #[test]
+    fn zoom_does_not_overshoot_between_distant_scales() {
+        // The Catmull-Rom tangent at the end of the fast zoom would carry the
+        // zoom past the last keyframe early in the slow drift.
+        let track = KeyframeTrack::new(&dive()).unwrap();
+        let samples: Vec<_> = (0..=1000)
+            .map(|i| track.sample(i as f64 * 0.02).focal_distance)
+            .collect();
+        for pair in samples.windows(2) {
+            assert!(pair[1] <= pair[0] * (1.0 + 1e-12), "{pair:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_bad_keyframes() {
+        let keyframes = dive();
+        assert!(KeyframeTrack::new(&[]).is_err());
+        assert!(KeyframeTrack::new(&[keyframes[1], keyframes[0]]).is_err());
+        let mut flat = keyframes[0];
+        flat.state.focal_distance = 0.0;
+        assert!(KeyframeTrack::new(&[flat]).is_err());
+    }
+
+    #[test]
+    fn hermite_meets_its_ends_and_tangents() {
+        let (p0, m0, p1, m1) = (1.0, 0.5, 3.0, -2.0);
+        let h = 1e-6;
+        assert_eq!(hermite(p0, m0, p1, m1, 0.0), p0);
+        assert_eq!(hermite(p0, m0, p1, m1, 1.0), p1);
+        let start_slope = (hermite(p0, m0, p1, m1, h) - p0) / h;
+        let end_slope = (p1 - hermite(p0, m0, p1, m1, 1.0 - h)) / h;
+        assert!((start_slope - m0).abs() < 1e-4);
+        assert!((end_slope - m1).abs() < 1e-4);
+    }
+}